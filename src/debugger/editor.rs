@@ -1,20 +1,32 @@
 use super::super::game;
-use super::super::game::block::Block;
-use super::super::game::camera::GameCamera;
-use super::super::game::loading::game_loader::Game;
+use super::super::game::abilities::Ability;
+use super::super::game::block;
+use super::super::game::block::{Block, Kind};
+use super::super::game::block_map;
+use super::super::game::block_map::BlockMap;
+use super::super::game::camera::{GameCamera, Upscale};
+use super::super::game::entities::{Definition, Door, MovingPlatform};
+use super::super::game::input::{Action, Actions};
+use super::super::game::loading::binary_level_loader;
+use super::super::game::loading::block_kinds_loader::{BlockKinds, DEFAULT_KIND};
 use super::super::game::loading::level_loader;
-use super::super::game::loading::level_loader::Level;
+use super::super::game::loading::level_loader::{Level, LevelEntity};
+use super::super::game::loading::world_loader::WorldMap;
 use super::super::game::loading::{
-    BlockMaterial, LoadingGame, MyTextureAtlasLayout, TextureAtlasImage, LEVELS_DIRECTORY,
-    TEXTURE_ATLAS_COLUMNS, TEXTURE_ATLAS_ROWS,
+    spawn_entity, KillPlanes, LoadingBlockKinds, LoadingWorld, Location, MyTextureAtlasLayout,
+    RoomBounds, RoomEntity, TextureAtlasImage, LEVELS_DIRECTORY, TEXTURE_ATLAS_COLUMNS,
+    TEXTURE_ATLAS_ROWS,
 };
-use super::super::game::plane::{Rotation, Translation};
+use super::super::game::plane::{block_intersects_plane, Plane};
+use super::super::game::save::Progress;
 use crate::game::block::{BlockBundle, TextureAtlasIndices};
-use bevy::log::tracing_subscriber::fmt::MakeWriter;
 use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
-use serde_json::to_writer;
-use std::fs::OpenOptions;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::path::PathBuf;
 
 #[derive(Component)]
 #[require(Node(ui_root_node))]
@@ -33,13 +45,84 @@ pub struct TextureAtlasIndex(usize);
 #[derive(Component)]
 struct SelectedTextureAtlasButton;
 
+/// The kind of the blocks placed
+#[derive(Resource, Debug)]
+pub struct SelectedBlockKind(String);
+
+impl Default for SelectedBlockKind {
+    fn default() -> Self {
+        Self(DEFAULT_KIND.into())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntityKind {
+    Door,
+    Switch,
+    Key,
+    MovingPlatform,
+    SpawnPoint,
+    Pickup,
+    Checkpoint,
+}
+
+impl EntityKind {
+    const ALL: [Self; 7] = [
+        Self::Door,
+        Self::Switch,
+        Self::Key,
+        Self::MovingPlatform,
+        Self::SpawnPoint,
+        Self::Pickup,
+        Self::Checkpoint,
+    ];
+}
+
+/// The kind of the entities placed instead of blocks, if any
+#[derive(Resource, Default, Debug)]
+pub struct SelectedEntityKind(Option<EntityKind>);
+
+/// Blocks per second moving platforms can be placed with
+const PLATFORM_SPEEDS: [f32; 4] = [1.0, 2.0, 4.0, 8.0];
+
+/// How the entities placed are configured, cycled for the selected kind
+#[derive(Resource, Debug)]
+pub struct EntityOptions {
+    ability: Ability,
+    speed: f32,
+    spawn_point: Option<String>,
+    /// The door switches open and keys lock, the last placed one by default
+    door: Option<String>,
+}
+
+impl Default for EntityOptions {
+    fn default() -> Self {
+        Self {
+            ability: Ability::DoubleJump,
+            speed: PLATFORM_SPEEDS[1],
+            spawn_point: None,
+            door: None,
+        }
+    }
+}
+
 pub fn plugin(app: &mut App) {
     app.add_plugins(MeshPickingPlugin)
         .init_resource::<TextureAtlasIndex>()
+        .init_resource::<SelectedBlockKind>()
+        .init_resource::<SelectedEntityKind>()
+        .init_resource::<EntityOptions>()
         .add_observer(spawn)
         .add_systems(
             Update,
-            (draw, save)
+            (
+                draw,
+                draw_entities,
+                select_block_kind,
+                select_entity_kind,
+                select_entity_option,
+                save,
+            )
                 .run_if(in_state(super::State::Enabled).and(in_state(game::State::Playing))),
         );
 }
@@ -150,123 +233,477 @@ fn button_click(
     commands.entity(entity).insert(SelectedTextureAtlasButton);
 }
 
+/// The cell of the plane under the cursor
+fn cursor_cell(
+    window: &Window,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+    upscale: &Upscale,
+    plane: &Plane,
+) -> Option<IVec3> {
+    let cursor_position = window
+        .cursor_position()
+        .and_then(|position| upscale.viewport_position(position))?;
+
+    let ray = camera
+        .viewport_to_world(camera_transform, cursor_position)
+        .unwrap();
+
+    let distance = ray.intersect_plane(plane.origin, InfinitePlane3d::new(plane.normal))?;
+
+    // The plane goes through the middle of the blocks it shows
+    Some(block_map::cell(ray.get_point(distance)))
+}
+
 fn draw(
     mut commands: Commands,
     mouse: Res<ButtonInput<MouseButton>>,
-    mut param_set: ParamSet<(
-        MeshRayCast,
-        (
-            ResMut<Assets<Mesh>>,
-            Query<(&mut Mesh3d, &mut TextureAtlasIndices), With<Block>>,
-        ),
-    )>,
+    actions: Res<Actions>,
+    block_map: Res<BlockMap>,
+    mut blocks: Query<(&Kind, &mut TextureAtlasIndices), With<Block>>,
     index: Res<TextureAtlasIndex>,
-    layouts: Res<Assets<TextureAtlasLayout>>,
-    layout: Res<MyTextureAtlasLayout>,
-    plane_rotation: ResMut<Rotation>,
-    material: Res<BlockMaterial>,
+    plane: Res<Plane>,
     window: Single<&Window, With<PrimaryWindow>>,
     camera: Single<(&Camera, &GlobalTransform), With<GameCamera>>,
-    plane_translation: Single<&mut Transform, With<Translation>>,
+    upscale: Res<Upscale>,
+    block_kinds: Res<Assets<BlockKinds>>,
+    loading_block_kinds: Res<LoadingBlockKinds>,
+    selected_block_kind: Res<SelectedBlockKind>,
+    selected_entity_kind: Res<SelectedEntityKind>,
 ) {
-    let Some(cursor_position) = window.cursor_position() else {
+    if selected_entity_kind.0.is_some() {
         return;
-    };
-
-    let mut mesh_ray_cast = param_set.p0();
-    let ray = camera
-        .0
-        .viewport_to_world(camera.1, cursor_position)
-        .unwrap();
-
-    let hit = mesh_ray_cast
-        .cast_ray(ray, &RayCastSettings::default())
-        .first();
+    }
 
-    if let Some(hit) = hit {
-        let (entity, hit) = hit.to_owned();
+    let Some(cell) = cursor_cell(&window, *camera, &upscale, &plane) else {
+        return;
+    };
 
+    if let Some(entity) = block_map.get(cell) {
         if mouse.pressed(MouseButton::Left) {
-            let (mut meshes, mut blocks) = param_set.p1();
-
-            if let Ok((mesh_handle, mut indices)) = blocks.get_mut(entity) {
-                *indices = match hit.normal.abs() {
-                    Vec3::X => TextureAtlasIndices {
-                        x: index.0,
-                        ..*indices
-                    },
-                    Vec3::Y => TextureAtlasIndices {
-                        y: index.0,
-                        ..*indices
-                    },
-                    Vec3::Z => TextureAtlasIndices {
-                        z: index.0,
-                        ..*indices
-                    },
-                    _ => panic!(),
-                };
-
-                let mesh = meshes.get_mut(mesh_handle.0.id()).unwrap();
-                *mesh = BlockBundle::mesh(&layouts, layout.0.clone(), &*indices);
+            let entities = if actions.pressed(Action::EditorFill) {
+                connected(&block_map, &plane, &blocks, cell)
+            } else {
+                vec![entity]
             };
+
+            for entity in entities {
+                if let Ok((_kind, mut indices)) = blocks.get_mut(entity) {
+                    *indices.face_mut(block::facing_axis(plane.normal)) = index.0;
+                };
+            }
         } else if mouse.pressed(MouseButton::Right) {
             commands.entity(entity).despawn_recursive();
         }
     } else if mouse.pressed(MouseButton::Left) {
-        let Some(distance) = ray.intersect_plane(
-            plane_translation.translation,
-            InfinitePlane3d::new(plane_rotation.get() * Vec3::Z),
-        ) else {
+        let block_kinds = block_kinds.get(loading_block_kinds.0.id()).unwrap();
+
+        let Some(kind) = block_kinds.0.get(&selected_block_kind.0) else {
             return;
         };
 
-        let point = ray.get_point(distance).floor();
-        let (mut meshes, ..) = param_set.p1();
-
-        commands.spawn(BlockBundle::new(
-            &point,
-            &mut meshes,
-            &layouts,
-            layout.0.clone(),
-            TextureAtlasIndices {
-                x: index.0,
-                y: index.0,
-                z: index.0,
-            },
-            material.0.clone(),
+        commands.spawn((
+            BlockBundle::new(&cell.as_vec3(), &selected_block_kind.0, kind, None),
+            RoomEntity,
         ));
     }
 }
 
+/// Places entities of the selected kind, configured by the [`EntityOptions`]
+fn draw_entities(
+    mut commands: Commands,
+    mouse: Res<ButtonInput<MouseButton>>,
+    actions: Res<Actions>,
+    progress: Res<Progress>,
+    plane: Res<Plane>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform), With<GameCamera>>,
+    upscale: Res<Upscale>,
+    selected_entity_kind: Res<SelectedEntityKind>,
+    mut options: ResMut<EntityOptions>,
+    mut definitions: Query<(Entity, &mut Definition)>,
+    mut doors: Query<&mut Door>,
+    mut platforms: Query<&mut MovingPlatform>,
+    mut last_door: Local<Option<Entity>>,
+    mut last_platform: Local<Option<Entity>>,
+) {
+    let Some(kind) = selected_entity_kind.0 else {
+        return;
+    };
+
+    let Some(cell) = cursor_cell(&window, *camera, &upscale, &plane) else {
+        return;
+    };
+
+    if mouse.just_pressed(MouseButton::Right) {
+        for (entity, definition) in &definitions {
+            if definition.0.contains(cell) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+
+        return;
+    }
+
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    if actions.pressed(Action::EditorFill) {
+        match kind {
+            // Grows the last door to the cell, respawning it at its new size
+            EntityKind::Door => {
+                let Some(Ok((entity, definition))) = last_door.map(|door| definitions.get(door))
+                else {
+                    return;
+                };
+
+                let mut definition = definition.0.clone();
+
+                if let LevelEntity::Door { min, max, .. } = &mut definition {
+                    *min = min.min(cell);
+                    *max = max.max(cell);
+                }
+
+                commands.entity(entity).despawn_recursive();
+                *last_door = Some(spawn_entity(&mut commands, &definition, &progress));
+            }
+
+            // Adds the cell to the waypoints of the last platform
+            EntityKind::MovingPlatform => {
+                let Some(Ok((entity, mut definition))) =
+                    last_platform.map(|platform| definitions.get_mut(platform))
+                else {
+                    return;
+                };
+
+                if let LevelEntity::MovingPlatform { waypoints, .. } = &mut definition.0 {
+                    waypoints.push(cell);
+                }
+
+                if let Ok(mut platform) = platforms.get_mut(entity) {
+                    platform.waypoints.push(cell.as_vec3() + 0.5);
+                }
+            }
+
+            _ => {}
+        }
+
+        return;
+    }
+
+    if definitions
+        .iter()
+        .any(|(_entity, definition)| definition.0.contains(cell))
+    {
+        return;
+    }
+
+    let door_exists = |id: &String| {
+        definitions.iter().any(|(_entity, definition)| {
+            matches!(&definition.0, LevelEntity::Door { id: door, .. } if door == id)
+        })
+    };
+
+    // Switches and keys need a door to open or lock
+    let door = options.door.clone().filter(door_exists);
+
+    if matches!(kind, EntityKind::Switch | EntityKind::Key) && door.is_none() {
+        warn!("No door for the {kind:?} to go with, place one first");
+        return;
+    }
+
+    let suffix = format!("{}-{}-{}", cell.x, cell.y, cell.z);
+
+    let definition = match kind {
+        EntityKind::Door => LevelEntity::Door {
+            id: format!("door-{suffix}"),
+            min: cell,
+            max: cell,
+            key: None,
+        },
+        EntityKind::Switch => {
+            let Some(door) = door.clone() else {
+                return;
+            };
+
+            LevelEntity::Switch { cell, door }
+        }
+        EntityKind::Key => LevelEntity::Key {
+            cell,
+            id: format!("key-{suffix}"),
+        },
+        EntityKind::MovingPlatform => LevelEntity::MovingPlatform {
+            waypoints: vec![cell],
+            speed: options.speed,
+        },
+        EntityKind::SpawnPoint => {
+            let Some(name) = options.spawn_point.clone() else {
+                warn!("No exit leads to this room, so a spawn point here would never be used");
+                return;
+            };
+
+            LevelEntity::SpawnPoint { name, cell }
+        }
+        EntityKind::Pickup => LevelEntity::Pickup {
+            cell,
+            ability: options.ability,
+        },
+        EntityKind::Checkpoint => LevelEntity::Checkpoint { cell },
+    };
+
+    // Keys lock the selected door
+    if let (LevelEntity::Key { id, .. }, Some(door)) = (&definition, &door) {
+        for (entity, mut door_definition) in &mut definitions {
+            if !matches!(&door_definition.0, LevelEntity::Door { id, .. } if id == door) {
+                continue;
+            }
+
+            if let LevelEntity::Door { key, .. } = &mut door_definition.0 {
+                *key = Some(id.clone());
+            }
+
+            if let Ok(mut locked) = doors.get_mut(entity) {
+                locked.key = Some(id.clone());
+            }
+        }
+    }
+
+    let entity = spawn_entity(&mut commands, &definition, &progress);
+
+    match &definition {
+        // Later switches and keys go with the newest door
+        LevelEntity::Door { id, .. } => {
+            *last_door = Some(entity);
+            options.door = Some(id.clone());
+        }
+        LevelEntity::MovingPlatform { .. } => *last_platform = Some(entity),
+        _ => {}
+    }
+}
+
+/// Cycles the option of the selected kind of entities, and picks one for new kinds that need it
+fn select_entity_option(
+    actions: Res<Actions>,
+    selected_entity_kind: Res<SelectedEntityKind>,
+    mut options: ResMut<EntityOptions>,
+    worlds: Res<Assets<WorldMap>>,
+    world: Res<LoadingWorld>,
+    location: Res<Location>,
+    definitions: Query<&Definition>,
+) {
+    let next = actions.just_pressed(Action::EditorNextEntityOption);
+
+    if !next && !selected_entity_kind.is_changed() {
+        return;
+    }
+
+    match selected_entity_kind.0 {
+        Some(EntityKind::Pickup) if next => {
+            options.ability = cycle(&Ability::ALL, Some(&options.ability)).unwrap();
+        }
+
+        Some(EntityKind::MovingPlatform) if next => {
+            options.speed = cycle(&PLATFORM_SPEEDS, Some(&options.speed)).unwrap();
+        }
+
+        // The names the world's exits into the room lead to, as no other would be used
+        Some(EntityKind::SpawnPoint) if next || options.spawn_point.is_none() => {
+            let mut names: Vec<String> = worlds
+                .get(world.0.id())
+                .into_iter()
+                .flat_map(|world_map| world_map.rooms.values())
+                .flat_map(|room| &room.exits)
+                .filter(|exit| exit.room == location.room)
+                .map(|exit| exit.spawn_point.clone())
+                .collect();
+
+            names.sort();
+            names.dedup();
+
+            let current = options.spawn_point.as_ref().filter(|_| next);
+            options.spawn_point = cycle(&names, current);
+        }
+
+        Some(EntityKind::Switch | EntityKind::Key) if next => {
+            let mut ids: Vec<String> = definitions
+                .iter()
+                .filter_map(|definition| match &definition.0 {
+                    LevelEntity::Door { id, .. } => Some(id.clone()),
+                    _ => None,
+                })
+                .collect();
+
+            ids.sort();
+            options.door = cycle(&ids, options.door.as_ref());
+        }
+
+        _ => {}
+    }
+}
+
+/// The item after `current` in `items`, or the first one
+fn cycle<T: Clone + PartialEq>(items: &[T], current: Option<&T>) -> Option<T> {
+    let index = current
+        .and_then(|current| items.iter().position(|item| item == current))
+        .map_or(0, |index| (index + 1) % items.len());
+
+    items.get(index).cloned()
+}
+
+/// The blocks on the plane of the same kind as the one at `start`, connected to it by faces
+fn connected(
+    block_map: &BlockMap,
+    plane: &Plane,
+    blocks: &Query<(&Kind, &mut TextureAtlasIndices), With<Block>>,
+    start: IVec3,
+) -> Vec<Entity> {
+    let kind_of = |entity: Entity| blocks.get(entity).ok().map(|(kind, _indices)| &kind.0);
+    let kind = block_map.get(start).and_then(kind_of);
+    let mut visited = HashSet::from([start]);
+    let mut stack = vec![start];
+    let mut entities = Vec::new();
+
+    while let Some(cell) = stack.pop() {
+        entities.extend(block_map.get(cell));
+
+        for (neighbour, entity) in block_map.neighbours(cell) {
+            let on_plane =
+                block_intersects_plane(neighbour.as_vec3() + 0.5, plane.origin, plane.normal);
+
+            if on_plane && kind_of(entity) == kind && visited.insert(neighbour) {
+                stack.push(neighbour);
+            }
+        }
+    }
+
+    entities
+}
+
+fn select_block_kind(
+    actions: Res<Actions>,
+    block_kinds: Res<Assets<BlockKinds>>,
+    loading_block_kinds: Res<LoadingBlockKinds>,
+    mut selected_block_kind: ResMut<SelectedBlockKind>,
+) {
+    if !actions.just_pressed(Action::EditorNextBlockKind) {
+        return;
+    }
+
+    let block_kinds = &block_kinds.get(loading_block_kinds.0.id()).unwrap().0;
+    let after_selected = (Bound::Excluded(&selected_block_kind.0), Bound::Unbounded);
+
+    let next = block_kinds
+        .range::<String, _>(after_selected)
+        .next()
+        .or(block_kinds.iter().next());
+
+    if let Some((id, _kind)) = next {
+        selected_block_kind.0 = id.clone();
+    }
+}
+
+fn select_entity_kind(actions: Res<Actions>, mut selected_entity_kind: ResMut<SelectedEntityKind>) {
+    if !actions.just_pressed(Action::EditorNextEntityKind) {
+        return;
+    }
+
+    // Back to blocks after the last kind
+    selected_entity_kind.0 = match selected_entity_kind.0 {
+        None => EntityKind::ALL.first().copied(),
+        Some(kind) => EntityKind::ALL
+            .iter()
+            .skip_while(|other| **other != kind)
+            .nth(1)
+            .copied(),
+    };
+}
+
 fn save(
-    keyboard: Res<ButtonInput<KeyCode>>,
-    games: Res<Assets<Game>>,
-    game: Res<LoadingGame>,
-    blocks: Query<(&GlobalTransform, &TextureAtlasIndices), With<Block>>,
+    actions: Res<Actions>,
+    worlds: Res<Assets<WorldMap>>,
+    world: Res<LoadingWorld>,
+    location: Res<Location>,
+    room_bounds: Res<RoomBounds>,
+    kill_planes: Res<KillPlanes>,
+    definitions: Query<&Definition>,
+    blocks: Query<(&GlobalTransform, &Kind, &TextureAtlasIndices), With<Block>>,
+    block_kinds: Res<Assets<BlockKinds>>,
+    loading_block_kinds: Res<LoadingBlockKinds>,
 ) {
-    // Todo: Hardcoded for Dvorak
-    if !(keyboard.pressed(KeyCode::ControlLeft) && keyboard.pressed(KeyCode::KeyM)) {
+    // The binary chord contains the JSON one, so it goes first
+    let binary = actions.just_pressed(Action::EditorSaveBinary);
+
+    if !binary && !actions.just_pressed(Action::EditorSave) {
         return;
     }
 
+    let block_kinds = &block_kinds.get(loading_block_kinds.0.id()).unwrap().0;
+
     let level = Level {
+        version: level_loader::VERSION,
+        bounds: room_bounds.0.clone(),
         blocks: blocks
             .iter()
-            .map(|(transform, indices)| level_loader::Block {
-                translation: transform.translation() - 0.5,
-                texture_atlas_indices: indices.clone(),
+            .map(|(transform, kind, indices)| {
+                let kind_indices = block_kinds
+                    .get(&kind.0)
+                    .map(|kind| &kind.texture_atlas_indices);
+
+                level_loader::Block {
+                    translation: transform.translation() - 0.5,
+                    kind: kind.0.clone(),
+                    // Only painted blocks override the texture atlas indices of their kind
+                    texture_atlas_indices: (kind_indices != Some(indices)).then(|| indices.clone()),
+                }
             })
             .collect(),
+        // Collected keys and pickups keep their definition, so they are saved too
+        entities: definitions
+            .iter()
+            .map(|definition| definition.0.clone())
+            .collect(),
+        kill_planes: kill_planes.0.clone(),
     };
 
-    let level_file = &games.get(game.0.id()).unwrap().level;
-    let level_path = format!("assets/{LEVELS_DIRECTORY}/{level_file}.json");
+    let Some(room) = worlds
+        .get(world.0.id())
+        .and_then(|world_map| world_map.rooms.get(&location.room))
+    else {
+        error!("Error saving level: no room {}", location.room);
+        return;
+    };
 
-    let file = OpenOptions::new()
-        .truncate(true)
-        .write(true)
-        .open(level_path)
-        .unwrap();
+    let mut level_path = PathBuf::from(format!("assets/{LEVELS_DIRECTORY}/{}", room.level));
 
-    to_writer::<_, Level>(file.make_writer(), &level).unwrap();
+    // What the room loads, JSON if there is no extension
+    let loaded_extension = level_path.extension().map_or("json".into(), |extension| {
+        extension.to_string_lossy().into_owned()
+    });
+
+    let bytes = if binary {
+        level_path.set_extension(binary_level_loader::EXTENSION);
+        binary_level_loader::encode(&level, binary_level_loader::COMPRESSED)
+    } else {
+        level_path.set_extension("json");
+        serde_json::to_vec(&level).map_err(io::Error::from)
+    };
+
+    let result = bytes.and_then(|bytes| fs::write(&level_path, bytes));
+
+    if let Err(error) = result {
+        error!("Error saving level to {}: {error}", level_path.display());
+        return;
+    }
+
+    if level_path
+        .extension()
+        .is_some_and(|extension| extension != loaded_extension.as_str())
+    {
+        warn!(
+            "Saved {}, but room {} loads {}, so set its level to the saved file to play it",
+            level_path.display(),
+            location.room,
+            room.level
+        );
+    }
 }
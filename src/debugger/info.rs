@@ -1,5 +1,6 @@
+use super::super::game::health::Health;
 use super::super::game::playing::state_machine;
-use super::editor::TextureAtlasIndex;
+use super::editor::{EntityOptions, SelectedBlockKind, SelectedEntityKind, TextureAtlasIndex};
 use bevy::prelude::*;
 
 #[derive(Component)]
@@ -9,9 +10,21 @@ pub struct UiRoot;
 #[derive(Component)]
 struct StateMachineText;
 
+#[derive(Component)]
+struct HealthText;
+
 #[derive(Component)]
 struct TextureAtlasIndexText;
 
+#[derive(Component)]
+struct BlockKindText;
+
+#[derive(Component)]
+struct EntityKindText;
+
+#[derive(Component)]
+struct EntityOptionsText;
+
 pub fn plugin(app: &mut App) {
     app.add_observer(spawn).add_systems(
         Update,
@@ -19,8 +32,16 @@ pub fn plugin(app: &mut App) {
             update_state_machine_text.run_if(
                 in_state(super::State::Enabled).and(resource_changed::<state_machine::State>),
             ),
+            update_health_text.run_if(in_state(super::State::Enabled)),
             update_texture_atlas_index_text
                 .run_if(in_state(super::State::Enabled).and(resource_changed::<TextureAtlasIndex>)),
+            update_block_kind_text
+                .run_if(in_state(super::State::Enabled).and(resource_changed::<SelectedBlockKind>)),
+            update_entity_kind_text.run_if(
+                in_state(super::State::Enabled).and(resource_changed::<SelectedEntityKind>),
+            ),
+            update_entity_options_text
+                .run_if(in_state(super::State::Enabled).and(resource_changed::<EntityOptions>)),
         ),
     );
 }
@@ -31,9 +52,25 @@ fn spawn(trigger: Trigger<OnAdd, UiRoot>, mut commands: Commands) {
             .spawn(Text::new("State machine state: "))
             .with_child((StateMachineText, TextSpan::default()));
 
+        parent
+            .spawn(Text::new("Health: "))
+            .with_child((HealthText, TextSpan::default()));
+
         parent
             .spawn(Text::new("Texture atlas index: "))
             .with_child((TextureAtlasIndexText, TextSpan::default()));
+
+        parent
+            .spawn(Text::new("Block kind: "))
+            .with_child((BlockKindText, TextSpan::default()));
+
+        parent
+            .spawn(Text::new("Entity kind: "))
+            .with_child((EntityKindText, TextSpan::default()));
+
+        parent
+            .spawn(Text::new("Entity options: "))
+            .with_child((EntityOptionsText, TextSpan::default()));
     });
 }
 
@@ -44,9 +81,37 @@ fn update_state_machine_text(
     text.0 = format!("{:?}", *state);
 }
 
+fn update_health_text(
+    health: Single<&Health, Changed<Health>>,
+    mut text: Single<&mut TextSpan, With<HealthText>>,
+) {
+    text.0 = format!("{}/{}", health.current, health.max);
+}
+
 fn update_texture_atlas_index_text(
     index: Res<TextureAtlasIndex>,
     mut text: Single<&mut TextSpan, With<TextureAtlasIndexText>>,
 ) {
     text.0 = format!("{:?}", *index);
 }
+
+fn update_block_kind_text(
+    kind: Res<SelectedBlockKind>,
+    mut text: Single<&mut TextSpan, With<BlockKindText>>,
+) {
+    text.0 = format!("{:?}", *kind);
+}
+
+fn update_entity_kind_text(
+    kind: Res<SelectedEntityKind>,
+    mut text: Single<&mut TextSpan, With<EntityKindText>>,
+) {
+    text.0 = format!("{:?}", *kind);
+}
+
+fn update_entity_options_text(
+    options: Res<EntityOptions>,
+    mut text: Single<&mut TextSpan, With<EntityOptionsText>>,
+) {
+    text.0 = format!("{:?}", *options);
+}
@@ -1,9 +1,17 @@
+pub mod abilities;
 pub mod block;
+pub mod block_map;
 pub mod camera;
+pub mod checkpoints;
+pub mod chunk;
+pub mod entities;
+pub mod health;
+pub mod input;
 pub mod loading;
 pub mod plane;
 pub mod player;
 pub mod playing;
+pub mod save;
 
 use bevy::prelude::*;
 use bevy::window::WindowResolution;
@@ -26,7 +34,6 @@ pub fn plugin(app: &mut App) {
         primary_window: Some(Window {
             title: "Untifted".into(),
             resolution: WindowResolution::new(SCALED_WIDTH, SCALED_HEIGHT),
-            resizable: false,
             ..default()
         }),
         ..default()
@@ -36,5 +43,18 @@ pub fn plugin(app: &mut App) {
 
     app.add_plugins(DefaultPlugins.set(window_plugin).set(image_plugin))
         .init_state::<State>()
-        .add_plugins((loading::plugin, playing::plugin));
+        .add_plugins((
+            input::plugin,
+            camera::plugin,
+            loading::plugin,
+            playing::plugin,
+            block_map::plugin,
+            chunk::plugin,
+            player::plugin,
+            save::plugin,
+            abilities::plugin,
+            health::plugin,
+            checkpoints::plugin,
+            entities::plugin,
+        ));
 }
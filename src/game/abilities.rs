@@ -0,0 +1,110 @@
+//! Movement upgrades, unlocked by reaching their pickups and kept in the save.
+
+use super::block;
+use super::block_map;
+use super::entities::remove_collected;
+use super::plane::Sliceable;
+use super::player::Player;
+use super::save::Progress;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Ability {
+    /// Jump once more in the air
+    DoubleJump,
+    /// Burst sideways, once per time in the air
+    Dash,
+    /// Jump off a wall the player is pushing against
+    WallJump,
+    /// Rotate the plane around its local X axis
+    RotateX,
+    RotateY,
+    RotateZ,
+}
+
+impl Ability {
+    pub const ALL: [Self; 6] = [
+        Self::DoubleJump,
+        Self::Dash,
+        Self::WallJump,
+        Self::RotateX,
+        Self::RotateY,
+        Self::RotateZ,
+    ];
+}
+
+/// Grants its ability when the player reaches its cell
+#[derive(Component)]
+#[require(Name(name), Sliceable)]
+pub struct Pickup(pub Ability);
+
+fn name() -> Name {
+    Name::new("Pickup")
+}
+
+#[derive(Resource)]
+struct PickupMesh(Handle<Mesh>);
+
+impl FromWorld for PickupMesh {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        Self(meshes.add(Sphere::new(block::SIZE / 4.0)))
+    }
+}
+
+#[derive(Resource)]
+struct PickupMaterial(Handle<StandardMaterial>);
+
+const PICKUP_COLOR: Color = Color::srgb(1.0, 0.85, 0.2);
+
+impl FromWorld for PickupMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let material = StandardMaterial {
+            base_color: PICKUP_COLOR,
+            unlit: true,
+            ..default()
+        };
+
+        Self(materials.add(material))
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<PickupMesh>()
+        .init_resource::<PickupMaterial>()
+        .add_observer(add_pickup)
+        .add_systems(Update, collect.run_if(in_state(super::State::Playing)));
+}
+
+fn add_pickup(
+    trigger: Trigger<OnAdd, Pickup>,
+    mut commands: Commands,
+    mesh: Res<PickupMesh>,
+    material: Res<PickupMaterial>,
+) {
+    commands
+        .entity(trigger.entity())
+        .insert((Mesh3d(mesh.0.clone()), MeshMaterial3d(material.0.clone())));
+}
+
+fn collect(
+    mut commands: Commands,
+    mut progress: ResMut<Progress>,
+    player: Single<&Transform, With<Player>>,
+    pickups: Query<(Entity, &Transform, &Pickup), Without<Player>>,
+) {
+    let cell = block_map::cell(player.translation);
+
+    for (entity, transform, pickup) in &pickups {
+        if block_map::cell(transform.translation) != cell {
+            continue;
+        }
+
+        info!("Unlocked {:?}", pickup.0);
+        progress.abilities.insert(pickup.0);
+        remove_collected::<Pickup>(&mut commands, entity);
+    }
+}
@@ -1,11 +1,13 @@
-use super::SCALE;
+use super::loading::block_kinds_loader::{BlockKind, ColliderShape};
+use super::plane::Plane;
 use bevy::asset::RenderAssetUsages;
 use bevy::prelude::*;
 use bevy::render::mesh::{Indices, PrimitiveTopology};
 use bevy_rapier3d::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::f32::EPSILON;
 
-#[derive(Deserialize, Component, Clone, Serialize)]
+#[derive(Deserialize, Component, Clone, Serialize, PartialEq)]
 pub struct TextureAtlasIndices {
     /// Right
     pub x: usize,
@@ -15,102 +17,236 @@ pub struct TextureAtlasIndices {
     pub z: usize,
 }
 
+impl TextureAtlasIndices {
+    pub fn face(&self, axis: usize) -> usize {
+        [self.x, self.y, self.z][axis]
+    }
+
+    pub fn face_mut(&mut self, axis: usize) -> &mut usize {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            _ => &mut self.z,
+        }
+    }
+}
+
+/// Gameplay behaviour of a block, from its kind
+#[derive(Deserialize, Component, Clone, Copy, Serialize, Default, Debug)]
+#[serde(default)]
+pub struct BlockFlags {
+    pub hazard: bool,
+    pub one_way: bool,
+    /// Climbed by holding up or down
+    pub ladder: bool,
+    /// Swum through
+    pub water: bool,
+}
+
+/// The ID of the block's kind
+#[derive(Component, Clone, Debug)]
+pub struct Kind(pub String);
+
 #[derive(Component)]
 pub struct Block;
 
+/// Collision group of blocks the player always collides with
+pub const SOLID: Group = Group::GROUP_1;
+/// Collision group of blocks the player only collides with while not moving up
+pub const ONE_WAY: Group = Group::GROUP_2;
+/// Collision group of blocks the player goes through
+pub const NON_SOLID: Group = Group::GROUP_3;
+
 #[derive(Bundle)]
 pub struct BlockBundle {
     block: Block,
+    kind: Kind,
     transform: Transform,
-    mesh3d: Mesh3d,
+    visibility: Visibility,
     texture_atlas_indices: TextureAtlasIndices,
-    mesh_material3d: MeshMaterial3d<StandardMaterial>,
+    flags: BlockFlags,
     collider: Collider,
+    collision_groups: CollisionGroups,
+    /// Until the plane cuts it
+    collider_disabled: ColliderDisabled,
 }
 
 pub const SIZE: f32 = 1.0;
 
 impl BlockBundle {
+    /// A block of `kind`, with `texture_atlas_indices` replacing the ones of the kind
     pub fn new(
         translation: &Vec3,
-        meshes: &mut Assets<Mesh>,
-        texture_atlas_layouts: &Assets<TextureAtlasLayout>,
-        texture_atlas_layout_handle: Handle<TextureAtlasLayout>,
-        texture_atlas_indices: TextureAtlasIndices,
-        material_handle: Handle<StandardMaterial>,
+        id: &str,
+        kind: &BlockKind,
+        texture_atlas_indices: Option<TextureAtlasIndices>,
     ) -> Self {
-        let mesh = Self::mesh(
-            texture_atlas_layouts,
-            texture_atlas_layout_handle,
-            &texture_atlas_indices,
-        );
-
-        let mesh_handle = meshes.add(mesh);
         let half_extents = SIZE / 2.0;
 
+        let collider = match kind.collider {
+            ColliderShape::Cube => Collider::cuboid(half_extents, half_extents, half_extents),
+            ColliderShape::Slab => Collider::compound(vec![(
+                Vec3::new(0.0, -half_extents / 2.0, 0.0),
+                Quat::IDENTITY,
+                Collider::cuboid(half_extents, half_extents / 2.0, half_extents),
+            )]),
+        };
+
+        let memberships = match (kind.solid, kind.flags.one_way) {
+            (false, _) => NON_SOLID,
+            (true, true) => ONE_WAY,
+            (true, false) => SOLID,
+        };
+
         Self {
             block: Block,
+            kind: Kind(id.into()),
             transform: Transform::from_translation(translation + 0.5),
-            mesh3d: Mesh3d(mesh_handle),
-            texture_atlas_indices,
-            mesh_material3d: MeshMaterial3d(material_handle),
-            collider: Collider::cuboid(half_extents, half_extents, half_extents),
+            visibility: Visibility::Hidden,
+            texture_atlas_indices: texture_atlas_indices
+                .unwrap_or_else(|| kind.texture_atlas_indices.clone()),
+            flags: kind.flags,
+            collider,
+            collision_groups: CollisionGroups::new(memberships, Group::ALL),
+            collider_disabled: ColliderDisabled,
         }
     }
+}
+
+/// The axis of the face that a cross-section with `normal` shows
+pub fn facing_axis(normal: Vec3) -> usize {
+    let normal = normal.abs();
+
+    if normal.x >= normal.y && normal.x >= normal.z {
+        0
+    } else if normal.y >= normal.z {
+        1
+    } else {
+        2
+    }
+}
+
+/// The cross-sections of many blocks merged into a single [`Mesh`]
+#[derive(Default)]
+pub struct BlockMesh {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
 
-    pub fn mesh(
+impl BlockMesh {
+    /// Adds the polygon where `plane` cuts the block centered at `center`, with `alpha` opacity
+    pub fn push(
+        &mut self,
+        center: Vec3,
+        plane: &Plane,
         layouts: &Assets<TextureAtlasLayout>,
-        layout_handle: Handle<TextureAtlasLayout>,
+        layout_handle: &Handle<TextureAtlasLayout>,
         indices: &TextureAtlasIndices,
-    ) -> Mesh {
-        let main = 0.0;
-        let cross_min = -0.5;
-        let cross_max = 0.5;
-
-        let positions = vec![
-            // X
-            [main, cross_max, cross_max],
-            [main, cross_max, cross_min],
-            [main, cross_min, cross_min],
-            [main, cross_min, cross_max],
-            // Y
-            [cross_min, main, cross_min],
-            [cross_max, main, cross_min],
-            [cross_max, main, cross_max],
-            [cross_min, main, cross_max],
-            // Z
-            [cross_min, cross_max, main],
-            [cross_max, cross_max, main],
-            [cross_max, cross_min, main],
-            [cross_min, cross_min, main],
-        ];
-
-        let uvs = Self::uv(layouts, layout_handle.clone(), indices.x)
-            .into_iter()
-            .chain(Self::uv(layouts, layout_handle.clone(), indices.y))
-            .chain(Self::uv(layouts, layout_handle.clone(), indices.z))
-            .collect::<Vec<_>>();
-
-        let indices = Indices::U32(vec![
-            0, 3, 1, 1, 3, 2, // X
-            4, 7, 5, 5, 7, 6, // Y
-            8, 11, 9, 9, 11, 10, // Z
-        ]);
+        alpha: f32,
+    ) {
+        let points = Self::cross_section(plane.origin - center, plane.normal);
+
+        if points.len() < 3 {
+            return;
+        }
+
+        let axis = facing_axis(plane.normal);
+        let texture_rectangle = Self::uv(layouts, layout_handle.clone(), indices.face(axis));
+        let offset = self.positions.len() as u32;
+
+        for point in &points {
+            let local = *point / SIZE;
+
+            // Matches the orientation of each face in the texture atlas
+            let face = match axis {
+                0 => Vec2::new(0.5 - local.z, 0.5 - local.y),
+                1 => Vec2::new(local.x + 0.5, local.z + 0.5),
+                _ => Vec2::new(local.x + 0.5, 0.5 - local.y),
+            };
+
+            let uv = texture_rectangle.min + texture_rectangle.size() * face;
+
+            self.positions.push((center + *point).to_array());
+            self.uvs.push(uv.to_array());
+            self.colors.push([1.0, 1.0, 1.0, alpha]);
+        }
+
+        for index in 1..points.len() as u32 - 1 {
+            self.indices
+                .extend([offset, offset + index, offset + index + 1]);
+        }
+    }
+
+    /// The corners of the polygon where a plane cuts a block centered at the origin, in order
+    fn cross_section(plane_origin: Vec3, plane_normal: Vec3) -> Vec<Vec3> {
+        let half_extent = SIZE / 2.0;
+        let mut points: Vec<Vec3> = Vec::new();
+
+        let mut push = |point: Vec3| {
+            if !points
+                .iter()
+                .any(|other| other.distance_squared(point) < EPSILON)
+            {
+                points.push(point);
+            }
+        };
+
+        for axis in 0..3 {
+            for (u, v) in [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)] {
+                let mut start = Vec3::ZERO;
+                start[(axis + 1) % 3] = u * half_extent;
+                start[(axis + 2) % 3] = v * half_extent;
+                start[axis] = -half_extent;
+
+                let mut end = start;
+                end[axis] = half_extent;
+
+                let start_distance = plane_normal.dot(start - plane_origin);
+                let end_distance = plane_normal.dot(end - plane_origin);
+
+                if (start_distance - end_distance).abs() < EPSILON {
+                    // The edge lies on the plane
+                    if start_distance.abs() < EPSILON {
+                        push(start);
+                        push(end);
+                    }
+                } else if start_distance * end_distance <= 0.0 {
+                    let t = start_distance / (start_distance - end_distance);
+                    push(start.lerp(end, t));
+                }
+            }
+        }
 
+        let centroid = points.iter().sum::<Vec3>() / points.len() as f32;
+        let (u, v) = plane_normal.any_orthonormal_pair();
+        let angle = |point: &Vec3| (*point - centroid).dot(v).atan2((*point - centroid).dot(u));
+
+        points.sort_by(|a, b| angle(a).total_cmp(&angle(b)));
+        points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn build(self) -> Mesh {
         Mesh::new(
             PrimitiveTopology::TriangleList,
             RenderAssetUsages::default(),
         )
-        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
-        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
-        .with_inserted_indices(indices)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, self.colors)
+        .with_inserted_indices(Indices::U32(self.indices))
     }
 
     fn uv(
         layouts: &Assets<TextureAtlasLayout>,
         layout_handle: Handle<TextureAtlasLayout>,
         index: usize,
-    ) -> [[f32; 2]; 4] {
+    ) -> Rect {
         let atlas = TextureAtlas {
             layout: layout_handle,
             index,
@@ -119,19 +255,11 @@ impl BlockBundle {
         let texture_rectangle = atlas.texture_rect(layouts).unwrap().as_rect();
         let atlas_size = layouts.get(atlas.layout.id()).unwrap().size.as_vec2();
 
-        let x_min = texture_rectangle.min.x / atlas_size.x;
-        let x_max = texture_rectangle.max.x / atlas_size.x;
-        let y_min = texture_rectangle.min.y / atlas_size.y;
-        let y_max = texture_rectangle.max.y / atlas_size.y;
-
-        return [
-            [x_min, y_min],
-            [x_max, y_min],
-            [x_max, y_max],
-            [x_min, y_max],
-        ];
+        return Rect {
+            min: texture_rectangle.min / atlas_size,
+            max: texture_rectangle.max / atlas_size,
+        };
     }
 }
 
 pub const TILE_SIZE: u32 = 8;
-pub const SCALED_TILE_SIZE: f32 = (TILE_SIZE * SCALE) as f32;
@@ -0,0 +1,110 @@
+//! Finds blocks by the integer cell they occupy, kept in sync as blocks spawn and despawn.
+
+use super::block::Block;
+use super::chunk;
+use super::plane::{block_intersects_plane, Plane};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+
+/// The cells next to a cell, sharing a face with it
+pub const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+#[derive(Resource, Default)]
+pub struct BlockMap {
+    blocks: HashMap<IVec3, Entity>,
+    /// The occupied cells of every chunk, to skip whole chunks in plane queries
+    chunks: HashMap<IVec3, HashSet<IVec3>>,
+}
+
+impl BlockMap {
+    /// The block occupying `cell`, if any
+    pub fn get(&self, cell: IVec3) -> Option<Entity> {
+        self.blocks.get(&cell).copied()
+    }
+
+    /// The blocks in the chunk at `position`
+    pub fn in_chunk(&self, position: IVec3) -> impl Iterator<Item = (IVec3, Entity)> + '_ {
+        self.chunks
+            .get(&position)
+            .into_iter()
+            .flatten()
+            .map(|cell| (*cell, self.blocks[cell]))
+    }
+
+    /// The blocks that `plane` cuts
+    pub fn on_plane<'a>(&'a self, plane: &'a Plane) -> impl Iterator<Item = (IVec3, Entity)> + 'a {
+        self.chunks
+            .iter()
+            .filter(|(position, _cells)| chunk::intersects(**position, plane, 0.0))
+            .flat_map(|(_position, cells)| cells)
+            .filter(|cell| block_intersects_plane(cell.as_vec3() + 0.5, plane.origin, plane.normal))
+            .map(|cell| (*cell, self.blocks[cell]))
+    }
+
+    /// The blocks sharing a face with `cell`
+    pub fn neighbours(&self, cell: IVec3) -> impl Iterator<Item = (IVec3, Entity)> + '_ {
+        NEIGHBOURS.iter().filter_map(move |offset| {
+            self.get(cell + *offset)
+                .map(|entity| (cell + *offset, entity))
+        })
+    }
+}
+
+/// The integer cell of a block from its center
+pub fn cell(translation: Vec3) -> IVec3 {
+    translation.floor().as_ivec3()
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<BlockMap>()
+        .add_observer(add_block)
+        .add_observer(remove_block);
+}
+
+fn add_block(
+    trigger: Trigger<OnAdd, Block>,
+    transforms: Query<&Transform>,
+    mut block_map: ResMut<BlockMap>,
+) {
+    let entity = trigger.entity();
+    let cell = cell(transforms.get(entity).unwrap().translation);
+
+    block_map.blocks.insert(cell, entity);
+
+    block_map
+        .chunks
+        .entry(chunk::position(cell))
+        .or_default()
+        .insert(cell);
+}
+
+fn remove_block(
+    trigger: Trigger<OnRemove, Block>,
+    transforms: Query<&Transform>,
+    mut block_map: ResMut<BlockMap>,
+) {
+    let entity = trigger.entity();
+    let cell = cell(transforms.get(entity).unwrap().translation);
+
+    // A block placed over this one already took its cell
+    if block_map.get(cell) != Some(entity) {
+        return;
+    }
+
+    block_map.blocks.remove(&cell);
+
+    let position = chunk::position(cell);
+    let cells = block_map.chunks.get_mut(&position).unwrap();
+    cells.remove(&cell);
+
+    if cells.is_empty() {
+        block_map.chunks.remove(&position);
+    }
+}
@@ -1,5 +1,19 @@
-use super::block::SCALED_TILE_SIZE;
+//! The game is rendered at its logical resolution into an image, then upscaled to the window by
+//! the largest whole factor that fits, so every pixel stays square and crisp.
+
+use super::block::TILE_SIZE;
+use super::input::{Action, Actions};
+use super::loading::RoomBounds;
+use super::plane::{Rotate, Rotation};
+use super::player::Player;
+use super::{HEIGHT, WIDTH};
 use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use bevy::render::view::RenderLayers;
+use bevy::window::{MonitorSelection, PrimaryWindow, WindowMode};
 
 #[derive(Component)]
 #[require(Name(name), Transform(transform), Camera3d, Projection(projection))]
@@ -15,9 +29,200 @@ fn transform() -> Transform {
 
 fn projection() -> Projection {
     Projection::from(OrthographicProjection {
-        scale: 1.0 / SCALED_TILE_SIZE,
+        scale: 1.0 / TILE_SIZE as f32,
         ..OrthographicProjection::default_3d()
     })
 }
 
 const DISTANCE: f32 = 100.0;
+
+/// How far the player can get from the center of the view before the camera follows, along the
+/// plane's X and Y axes
+const DEAD_ZONE: Vec2 = Vec2::new(2.0, 1.5);
+/// How quickly the camera catches up, higher is faster
+const SMOOTHING: f32 = 8.0;
+
+/// What the [`GameCamera`] renders to
+#[derive(Resource)]
+pub struct RenderImage(pub Handle<Image>);
+
+impl FromWorld for RenderImage {
+    fn from_world(world: &mut World) -> Self {
+        let size = Extent3d {
+            width: WIDTH,
+            height: HEIGHT,
+            ..default()
+        };
+
+        let mut image = Image {
+            texture_descriptor: TextureDescriptor {
+                label: Some("render_image"),
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Bgra8UnormSrgb,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_DST
+                    | TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            },
+            ..default()
+        };
+
+        image.resize(size);
+
+        let mut images = world.resource_mut::<Assets<Image>>();
+        Self(images.add(image))
+    }
+}
+
+/// How the [`RenderImage`] fits in the window
+#[derive(Resource, Default, PartialEq)]
+pub struct Upscale {
+    /// Window pixels per game pixel
+    factor: f32,
+    /// The top left corner of the image in the window
+    offset: Vec2,
+}
+
+impl Upscale {
+    /// The position in the [`RenderImage`] under `window_position`, if it is over the image
+    pub fn viewport_position(&self, window_position: Vec2) -> Option<Vec2> {
+        let position = (window_position - self.offset) / self.factor;
+        let size = Vec2::new(WIDTH as f32, HEIGHT as f32);
+
+        (position.cmpge(Vec2::ZERO).all() && position.cmplt(size).all()).then_some(position)
+    }
+}
+
+/// Only the upscaling camera sees the upscaled image
+const UPSCALE_LAYER: usize = 1;
+
+#[derive(Component)]
+struct UpscaledImage;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<RenderImage>()
+        .init_resource::<Upscale>()
+        .add_observer(render_to_image)
+        .add_systems(Startup, spawn_upscaling)
+        .add_systems(Update, (upscale, toggle_fullscreen))
+        .add_systems(Update, follow.run_if(in_state(super::State::Playing)));
+}
+
+fn render_to_image(
+    trigger: Trigger<OnAdd, GameCamera>,
+    render_image: Res<RenderImage>,
+    mut cameras: Query<&mut Camera>,
+) {
+    let mut camera = cameras.get_mut(trigger.entity()).unwrap();
+    camera.target = RenderTarget::Image(render_image.0.clone());
+    camera.order = -1;
+}
+
+fn spawn_upscaling(mut commands: Commands, render_image: Res<RenderImage>) {
+    commands.spawn((
+        Name::new("Upscaling camera"),
+        Camera2d,
+        Camera {
+            // The bars around the image
+            clear_color: ClearColorConfig::Custom(Color::BLACK),
+            ..default()
+        },
+        RenderLayers::layer(UPSCALE_LAYER),
+    ));
+
+    commands.spawn((
+        Name::new("Upscaled image"),
+        UpscaledImage,
+        Sprite::from_image(render_image.0.clone()),
+        RenderLayers::layer(UPSCALE_LAYER),
+    ));
+}
+
+fn upscale(
+    window: Single<&Window, With<PrimaryWindow>>,
+    mut upscale: ResMut<Upscale>,
+    mut image: Single<&mut Transform, With<UpscaledImage>>,
+) {
+    let size = Vec2::new(WIDTH as f32, HEIGHT as f32);
+
+    // Whole physical pixels, as logical ones can be fractions of them
+    let physical_factor = (window.physical_size().as_vec2() / size)
+        .min_element()
+        .floor()
+        .max(1.0);
+
+    let factor = physical_factor / window.scale_factor();
+
+    upscale.set_if_neq(Upscale {
+        factor,
+        offset: (window.size() - size * factor) / 2.0,
+    });
+
+    image.scale = Vec3::new(factor, factor, 1.0);
+}
+
+fn toggle_fullscreen(actions: Res<Actions>, mut window: Single<&mut Window, With<PrimaryWindow>>) {
+    if !actions.just_pressed(Action::ToggleFullscreen) {
+        return;
+    }
+
+    window.mode = match window.mode {
+        WindowMode::Windowed => WindowMode::BorderlessFullscreen(MonitorSelection::Current),
+        _ => WindowMode::Windowed,
+    };
+}
+
+/// Moves the camera's pivot after the player within the plane, keeping the view inside the
+/// [`RoomBounds`]
+///
+/// Works in the axes of the current, possibly transitioning, rotation, so the camera keeps
+/// following while the plane turns.
+fn follow(
+    time: Res<Time>,
+    rotation: Res<Rotation>,
+    room_bounds: Res<RoomBounds>,
+    player: Single<&Transform, (With<Player>, Without<Rotate>)>,
+    mut pivot: Single<&mut Transform, With<Rotate>>,
+) {
+    let rotation = rotation.get();
+    let half_view = Vec2::new(WIDTH as f32, HEIGHT as f32) / TILE_SIZE as f32 / 2.0;
+
+    // Depth only picks what is in front of the camera, so it follows the player exactly
+    let normal = rotation * Vec3::Z;
+    let mut target =
+        pivot.translation + normal * (player.translation - pivot.translation).dot(normal);
+
+    for (index, axis) in [rotation * Vec3::X, rotation * Vec3::Y]
+        .into_iter()
+        .enumerate()
+    {
+        let offset = (player.translation - target).dot(axis);
+        target += axis * (offset - offset.clamp(-DEAD_ZONE[index], DEAD_ZONE[index]));
+
+        let Some(bounds) = &room_bounds.0 else {
+            continue;
+        };
+
+        // The room's extent along the axis, from its first cell to past its last
+        let center = (bounds.min + bounds.max + 1).as_vec3().dot(axis) / 2.0;
+        let half_size = (bounds.max - bounds.min + 1).as_vec3().dot(axis.abs()) / 2.0;
+        let margin = half_size - half_view[index];
+
+        // Rooms smaller than the view are centered
+        let position = target.dot(axis);
+        let clamped = if margin < 0.0 {
+            center
+        } else {
+            position.clamp(center - margin, center + margin)
+        };
+
+        target += axis * (clamped - position);
+    }
+
+    pivot
+        .translation
+        .smooth_nudge(&target, SMOOTHING, time.delta_secs());
+}
@@ -0,0 +1,264 @@
+//! Checkpoints, and respawning at the last one reached after dying.
+
+use super::block;
+use super::block_map;
+use super::health::{Died, Health, Invulnerable};
+use super::loading::RoomEntity;
+use super::plane::{Orientation, Rotate, Rotation, Sliceable};
+use super::player::Player;
+use super::playing::state_machine;
+use super::save::Save;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+/// Where the player comes back after dying, the room's entrance until a checkpoint is reached
+#[derive(Resource, Default)]
+pub struct RespawnPoint {
+    pub translation: Vec3,
+    pub orientation: Orientation,
+}
+
+/// Sets the [`RespawnPoint`] when the player reaches its cell
+#[derive(Component)]
+#[require(Name(name), Sliceable)]
+pub struct Checkpoint;
+
+fn name() -> Name {
+    Name::new("Checkpoint")
+}
+
+/// The checkpoint the player last reached
+#[derive(Component)]
+struct Active;
+
+/// Fading out and back in, respawning while the screen is black
+#[derive(Component)]
+pub struct Respawning(Timer);
+
+const FADE_DURATION: f32 = 0.8;
+
+#[derive(Component)]
+#[require(
+    Node(fade_node),
+    BackgroundColor,
+    GlobalZIndex(fade_z_index),
+    RoomEntity
+)]
+struct Fade;
+
+fn fade_node() -> Node {
+    Node {
+        position_type: PositionType::Absolute,
+        width: Val::Percent(100.0),
+        height: Val::Percent(100.0),
+        ..default()
+    }
+}
+
+/// Over everything else
+fn fade_z_index() -> GlobalZIndex {
+    GlobalZIndex(i32::MAX)
+}
+
+#[derive(Resource)]
+struct CheckpointMesh(Handle<Mesh>);
+
+impl FromWorld for CheckpointMesh {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        Self(meshes.add(Cuboid::new(
+            block::SIZE / 4.0,
+            block::SIZE,
+            block::SIZE / 4.0,
+        )))
+    }
+}
+
+#[derive(Resource)]
+struct CheckpointMaterial(Handle<StandardMaterial>);
+
+const CHECKPOINT_COLOR: Color = Color::srgb(0.4, 0.4, 0.5);
+
+impl FromWorld for CheckpointMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let material = StandardMaterial {
+            base_color: CHECKPOINT_COLOR,
+            unlit: true,
+            ..default()
+        };
+
+        Self(materials.add(material))
+    }
+}
+
+#[derive(Resource)]
+struct ActiveCheckpointMaterial(Handle<StandardMaterial>);
+
+const ACTIVE_CHECKPOINT_COLOR: Color = Color::srgb(0.3, 1.0, 0.5);
+
+impl FromWorld for ActiveCheckpointMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let material = StandardMaterial {
+            base_color: ACTIVE_CHECKPOINT_COLOR,
+            unlit: true,
+            ..default()
+        };
+
+        Self(materials.add(material))
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<RespawnPoint>()
+        .init_resource::<CheckpointMesh>()
+        .init_resource::<CheckpointMaterial>()
+        .init_resource::<ActiveCheckpointMaterial>()
+        .add_observer(add_checkpoint)
+        .add_observer(start_respawning)
+        .add_systems(OnEnter(super::State::Playing), enter_room)
+        .add_systems(
+            Update,
+            (reach, respawn).run_if(in_state(super::State::Playing)),
+        );
+}
+
+fn add_checkpoint(
+    trigger: Trigger<OnAdd, Checkpoint>,
+    mut commands: Commands,
+    mesh: Res<CheckpointMesh>,
+    material: Res<CheckpointMaterial>,
+) {
+    commands
+        .entity(trigger.entity())
+        .insert((Mesh3d(mesh.0.clone()), MeshMaterial3d(material.0.clone())));
+}
+
+/// Respawns at the room's entrance until a checkpoint is reached
+fn enter_room(
+    rotation: Res<Rotation>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    player: Single<&Transform, With<Player>>,
+) {
+    *respawn_point = RespawnPoint {
+        translation: player.translation,
+        orientation: rotation.target(),
+    };
+}
+
+fn reach(
+    mut commands: Commands,
+    rotation: Res<Rotation>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    material: Res<CheckpointMaterial>,
+    active_material: Res<ActiveCheckpointMaterial>,
+    player: Single<&Transform, With<Player>>,
+    checkpoints: Query<(Entity, &Transform, Has<Active>), (With<Checkpoint>, Without<Player>)>,
+) {
+    let cell = block_map::cell(player.translation);
+
+    let Some((entity, transform, _active)) =
+        checkpoints.iter().find(|(_entity, transform, active)| {
+            !active && block_map::cell(transform.translation) == cell
+        })
+    else {
+        return;
+    };
+
+    for (entity, _transform, active) in &checkpoints {
+        if active {
+            commands
+                .entity(entity)
+                .remove::<Active>()
+                .insert(MeshMaterial3d(material.0.clone()));
+        }
+    }
+
+    commands
+        .entity(entity)
+        .insert((Active, MeshMaterial3d(active_material.0.clone())));
+
+    // The orientation the player reached it in, so they come back to the same view
+    *respawn_point = RespawnPoint {
+        translation: transform.translation,
+        orientation: rotation.target(),
+    };
+
+    commands.trigger(Save);
+}
+
+fn start_respawning(
+    _trigger: Trigger<Died>,
+    mut commands: Commands,
+    player: Single<(Entity, &mut KinematicCharacterController), With<Player>>,
+) {
+    let (entity, mut controller) = player.into_inner();
+
+    controller.translation = None;
+
+    commands
+        .entity(entity)
+        .insert(Respawning(Timer::from_seconds(
+            FADE_DURATION,
+            TimerMode::Once,
+        )));
+
+    commands.spawn(Fade);
+}
+
+fn respawn(
+    mut commands: Commands,
+    time: Res<Time>,
+    respawn_point: Res<RespawnPoint>,
+    mut rotation: ResMut<Rotation>,
+    mut state: ResMut<state_machine::State>,
+    player: Single<
+        (
+            Entity,
+            &mut Respawning,
+            &mut Transform,
+            &mut Velocity,
+            &mut Health,
+            &mut KinematicCharacterController,
+        ),
+        With<Player>,
+    >,
+    mut pivot: Single<&mut Transform, (With<Rotate>, Without<Player>)>,
+    fade: Single<(Entity, &mut BackgroundColor), With<Fade>>,
+) {
+    let (entity, mut respawning, mut transform, mut velocity, mut health, mut controller) =
+        player.into_inner();
+
+    let (fade_entity, mut fade_color) = fade.into_inner();
+
+    let was_dark = respawning.0.fraction() >= 0.5;
+    respawning.0.tick(time.delta());
+
+    // Out to black and back
+    let alpha = 1.0 - (respawning.0.fraction() * 2.0 - 1.0).abs();
+    fade_color.0 = Color::BLACK.with_alpha(alpha);
+
+    if !was_dark && respawning.0.fraction() >= 0.5 {
+        *rotation = Rotation::new(respawn_point.orientation);
+        *state = state_machine::State::Falling;
+
+        transform.translation = respawn_point.translation;
+        velocity.linvel = Vec3::ZERO;
+        health.current = health.max;
+        controller.up = rotation.get() * Vec3::Y;
+
+        // The camera cuts straight there
+        pivot.translation = respawn_point.translation;
+        pivot.rotation = rotation.get();
+    }
+
+    if respawning.0.finished() {
+        commands
+            .entity(entity)
+            .remove::<Respawning>()
+            .insert(Invulnerable::default());
+
+        commands.entity(fade_entity).despawn();
+    }
+}
@@ -0,0 +1,246 @@
+//! Groups blocks into fixed-size chunks, each drawn as a single merged [`Mesh`].
+
+use super::block::{Block, BlockMesh, TextureAtlasIndices};
+use super::block_map;
+use super::block_map::BlockMap;
+use super::input::{Action, Actions};
+use super::loading::{BlockMaterial, GhostMaterial, MyTextureAtlasLayout};
+use super::plane::{cuboid_intersects_plane, Plane};
+use bevy::prelude::*;
+use bevy::render::primitives::Aabb;
+use bevy::utils::{HashMap, HashSet};
+
+/// Blocks per chunk along each axis
+pub const SIZE: i32 = 16;
+
+#[derive(Component)]
+#[require(Transform, Visibility)]
+pub struct Chunk;
+
+#[derive(Default)]
+struct ChunkEntry {
+    entity: Option<Entity>,
+    /// Child of the chunk entity with the ghosts of its blocks
+    ghost: Option<Entity>,
+}
+
+#[derive(Resource, Default)]
+pub struct Chunks {
+    chunks: HashMap<IVec3, ChunkEntry>,
+    dirty: HashSet<IVec3>,
+}
+
+/// Whether `plane` cuts the chunk at `position` grown by `margin` on every side
+pub fn intersects(position: IVec3, plane: &Plane, margin: f32) -> bool {
+    let half_size = Vec3::splat(SIZE as f32 / 2.0);
+    let center = (position * SIZE).as_vec3() + half_size;
+    cuboid_intersects_plane(center, half_size + margin, plane.origin, plane.normal)
+}
+
+/// Whether blocks in the slices next to the plane are drawn as ghosts
+#[derive(Resource, Default)]
+pub struct Ghosts(pub bool);
+
+/// How many slices on each side of the plane have ghosts
+const GHOST_DEPTH: i32 = 2;
+/// Opacity of the ghosts in the nearest slices, fading out further away
+const GHOST_ALPHA: f32 = 0.5;
+
+/// The position of the chunk containing `cell`
+pub fn position(cell: IVec3) -> IVec3 {
+    cell.div_euclid(IVec3::splat(SIZE))
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<Chunks>()
+        .init_resource::<Ghosts>()
+        .add_observer(add_block)
+        .add_observer(remove_block)
+        .add_systems(
+            Update,
+            toggle_ghosts.run_if(in_state(super::State::Playing)),
+        )
+        .add_systems(PostUpdate, (mark_dirty, rebuild).chain());
+}
+
+fn toggle_ghosts(actions: Res<Actions>, mut ghosts: ResMut<Ghosts>) {
+    if actions.just_pressed(Action::ToggleGhosts) {
+        ghosts.0 = !ghosts.0;
+    }
+}
+
+fn add_block(
+    trigger: Trigger<OnAdd, Block>,
+    transforms: Query<&Transform>,
+    mut chunks: ResMut<Chunks>,
+) {
+    let cell = block_map::cell(transforms.get(trigger.entity()).unwrap().translation);
+    chunks.chunks.entry(position(cell)).or_default();
+    chunks.dirty.insert(position(cell));
+}
+
+fn remove_block(
+    trigger: Trigger<OnRemove, Block>,
+    transforms: Query<&Transform>,
+    mut chunks: ResMut<Chunks>,
+) {
+    let cell = block_map::cell(transforms.get(trigger.entity()).unwrap().translation);
+    chunks.dirty.insert(position(cell));
+}
+
+fn mark_dirty(
+    mut chunks: ResMut<Chunks>,
+    plane: Res<Plane>,
+    ghosts: Res<Ghosts>,
+    blocks: Query<
+        &Transform,
+        (
+            With<Block>,
+            Or<(Changed<TextureAtlasIndices>, Changed<Visibility>)>,
+        ),
+    >,
+) {
+    for transform in &blocks {
+        chunks
+            .dirty
+            .insert(position(block_map::cell(transform.translation)));
+    }
+
+    // Cross-sections follow the plane, even for blocks that stay visible
+    if plane.is_changed() || ghosts.is_changed() {
+        let Chunks { chunks, dirty } = &mut *chunks;
+        let ghost_depth = if ghosts.0 { GHOST_DEPTH as f32 } else { 0.0 };
+
+        dirty.extend(
+            chunks
+                .iter()
+                .filter(|(position, chunk)| {
+                    // Chunks with old ghosts need them cleared, wherever the plane went
+                    chunk.ghost.is_some() || intersects(**position, &plane, ghost_depth)
+                })
+                .map(|(position, _chunk)| *position),
+        );
+    }
+}
+
+/// Rebuilds the mesh of every chunk whose blocks changed since the last frame
+fn rebuild(
+    mut commands: Commands,
+    mut chunks: ResMut<Chunks>,
+    block_map: Res<BlockMap>,
+    blocks: Query<(&Transform, &TextureAtlasIndices, &Visibility), With<Block>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    layouts: Res<Assets<TextureAtlasLayout>>,
+    layout: Res<MyTextureAtlasLayout>,
+    material: Res<BlockMaterial>,
+    ghost_material: Res<GhostMaterial>,
+    plane: Res<Plane>,
+    ghosts: Res<Ghosts>,
+) {
+    let Chunks { chunks, dirty } = &mut *chunks;
+
+    for position in dirty.drain() {
+        let Some(chunk) = chunks.get_mut(&position) else {
+            continue;
+        };
+
+        if block_map.in_chunk(position).next().is_none() {
+            if let Some(entity) = chunk.entity {
+                commands.entity(entity).despawn_recursive();
+            }
+
+            chunks.remove(&position);
+            continue;
+        }
+
+        let origin = (position * SIZE).as_vec3();
+        let local_plane = Plane {
+            origin: plane.origin - origin,
+            ..*plane
+        };
+
+        let mut mesh = BlockMesh::default();
+        let mut ghost_mesh = BlockMesh::default();
+
+        for (_cell, block) in block_map.in_chunk(position) {
+            let Ok((transform, indices, visibility)) = blocks.get(block) else {
+                continue;
+            };
+
+            let center = transform.translation - origin;
+
+            if *visibility != Visibility::Hidden {
+                mesh.push(center, &local_plane, &layouts, &layout.0, indices, 1.0);
+            }
+
+            if !ghosts.0 {
+                continue;
+            }
+
+            for depth in (-GHOST_DEPTH..=GHOST_DEPTH).filter(|&depth| depth != 0) {
+                let ghost_plane = Plane {
+                    origin: local_plane.origin + local_plane.normal * depth as f32,
+                    ..local_plane
+                };
+
+                let alpha = GHOST_ALPHA * (1.0 - (depth.abs() - 1) as f32 / GHOST_DEPTH as f32);
+
+                ghost_mesh.push(center, &ghost_plane, &layouts, &layout.0, indices, alpha);
+            }
+        }
+
+        let entity = *chunk.entity.get_or_insert_with(|| {
+            commands
+                .spawn((
+                    Chunk,
+                    Transform::from_translation(origin),
+                    MeshMaterial3d(material.0.clone()),
+                ))
+                .id()
+        });
+
+        if ghost_mesh.is_empty() {
+            if let Some(ghost) = chunk.ghost.take() {
+                commands.entity(ghost).despawn();
+            }
+        } else {
+            let ghost = *chunk.ghost.get_or_insert_with(|| {
+                commands
+                    .spawn((
+                        Transform::default(),
+                        Visibility::default(),
+                        MeshMaterial3d(ghost_material.0.clone()),
+                    ))
+                    .set_parent(entity)
+                    .id()
+            });
+
+            commands
+                .entity(ghost)
+                .insert(Mesh3d(meshes.add(ghost_mesh.build())))
+                .remove::<Aabb>();
+        }
+
+        if mesh.is_empty() {
+            // Hiding the chunk would hide its ghosts too
+            let visibility = if chunk.ghost.is_some() {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+
+            commands
+                .entity(entity)
+                .remove::<Mesh3d>()
+                .insert(visibility);
+
+            continue;
+        }
+
+        // The bounding box is only computed when missing, so drop the stale one
+        commands
+            .entity(entity)
+            .insert((Mesh3d(meshes.add(mesh.build())), Visibility::Inherited))
+            .remove::<Aabb>();
+    }
+}
@@ -0,0 +1,425 @@
+//! The interactive entities levels place besides blocks: doors, switches, keys, moving platforms
+//! and spawn points.
+
+use super::block;
+use super::block::SOLID;
+use super::block_map;
+use super::input::{Action, Actions};
+use super::loading::level_loader::LevelEntity;
+use super::plane::Sliceable;
+use super::player::Player;
+use super::playing::state_machine::state_machine;
+use super::save::{Progress, Save};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+/// What placed the entity, kept to save the level again from the editor
+#[derive(Component, Clone, Debug)]
+pub struct Definition(pub LevelEntity);
+
+#[derive(Component)]
+#[require(Name(door_name), Sliceable, CollisionGroups(solid))]
+pub struct Door {
+    pub id: String,
+    /// The item that opens it on contact
+    pub key: Option<String>,
+    pub size: Vec3,
+    pub open: bool,
+}
+
+fn door_name() -> Name {
+    Name::new("Door")
+}
+
+fn solid() -> CollisionGroups {
+    CollisionGroups::new(SOLID, Group::ALL)
+}
+
+impl Door {
+    /// A closed door from its first to its last cell, and its translation
+    pub fn new(id: &str, key: Option<String>, min: IVec3, max: IVec3) -> (Self, Transform) {
+        let size = (max - min + 1).as_vec3();
+
+        let door = Self {
+            id: id.into(),
+            key,
+            size,
+            open: false,
+        };
+
+        (
+            door,
+            Transform::from_translation(min.as_vec3() + size / 2.0),
+        )
+    }
+}
+
+#[derive(Component)]
+#[require(Name(switch_name), Sliceable)]
+pub struct Switch {
+    /// The ID of the doors it opens and closes
+    pub door: String,
+}
+
+fn switch_name() -> Name {
+    Name::new("Switch")
+}
+
+#[derive(Component)]
+#[require(Name(key_name), Sliceable)]
+pub struct Key(pub String);
+
+fn key_name() -> Name {
+    Name::new("Key")
+}
+
+#[derive(Component)]
+#[require(
+    Name(moving_platform_name),
+    Sliceable,
+    RigidBody(kinematic),
+    Collider(cube),
+    CollisionGroups(solid)
+)]
+pub struct MovingPlatform {
+    pub waypoints: Vec<Vec3>,
+    pub speed: f32,
+    /// The index of the waypoint it is heading to
+    pub next: usize,
+}
+
+fn moving_platform_name() -> Name {
+    Name::new("Moving platform")
+}
+
+fn kinematic() -> RigidBody {
+    RigidBody::KinematicPositionBased
+}
+
+fn cube() -> Collider {
+    let half_extent = block::SIZE / 2.0;
+    Collider::cuboid(half_extent, half_extent, half_extent)
+}
+
+impl MovingPlatform {
+    /// A platform through the centers of the cells of `waypoints`, and its translation
+    pub fn new(waypoints: &[IVec3], speed: f32) -> (Self, Transform) {
+        let waypoints: Vec<Vec3> = waypoints.iter().map(|cell| cell.as_vec3() + 0.5).collect();
+        let translation = waypoints.first().copied().unwrap_or_default();
+
+        let platform = Self {
+            waypoints,
+            // Negative or NaN speeds would move it away from its waypoints forever
+            speed: speed.max(0.0),
+            next: 0,
+        };
+
+        (platform, Transform::from_translation(translation))
+    }
+}
+
+/// Where the player enters the room from exits leading to its name
+#[derive(Component)]
+#[require(Name(spawn_point_name), Transform)]
+pub struct SpawnPoint(pub String);
+
+fn spawn_point_name() -> Name {
+    Name::new("Spawn point")
+}
+
+#[derive(Resource)]
+struct EntityMeshes {
+    cube: Handle<Mesh>,
+    small: Handle<Mesh>,
+}
+
+impl FromWorld for EntityMeshes {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+
+        Self {
+            cube: meshes.add(Cuboid::from_length(block::SIZE)),
+            small: meshes.add(Cuboid::from_length(block::SIZE / 3.0)),
+        }
+    }
+}
+
+#[derive(Resource)]
+struct EntityMaterials {
+    door: Handle<StandardMaterial>,
+    open_door: Handle<StandardMaterial>,
+    switch: Handle<StandardMaterial>,
+    key: Handle<StandardMaterial>,
+    moving_platform: Handle<StandardMaterial>,
+}
+
+impl FromWorld for EntityMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+
+        let mut unlit = |base_color: Color| {
+            materials.add(StandardMaterial {
+                base_color,
+                unlit: true,
+                alpha_mode: if base_color.alpha() < 1.0 {
+                    AlphaMode::Blend
+                } else {
+                    AlphaMode::Opaque
+                },
+                ..default()
+            })
+        };
+
+        Self {
+            door: unlit(Color::srgb(0.55, 0.35, 0.2)),
+            open_door: unlit(Color::srgba(0.55, 0.35, 0.2, 0.25)),
+            switch: unlit(Color::srgb(0.9, 0.3, 0.3)),
+            key: unlit(Color::srgb(1.0, 0.85, 0.2)),
+            moving_platform: unlit(Color::srgb(0.5, 0.5, 0.6)),
+        }
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<EntityMeshes>()
+        .init_resource::<EntityMaterials>()
+        .add_observer(add_door)
+        .add_observer(add_switch)
+        .add_observer(add_key)
+        .add_observer(add_moving_platform)
+        .add_systems(
+            Update,
+            (press_switches, collect_keys, unlock_doors, show_doors)
+                .chain()
+                .run_if(in_state(super::State::Playing)),
+        )
+        .add_systems(
+            FixedUpdate,
+            move_platforms
+                .after(state_machine)
+                .run_if(in_state(super::State::Playing)),
+        );
+}
+
+fn add_door(
+    trigger: Trigger<OnAdd, Door>,
+    mut commands: Commands,
+    doors: Query<&Door>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    materials: Res<EntityMaterials>,
+) {
+    let size = doors.get(trigger.entity()).unwrap().size;
+    let half_size = size / 2.0;
+
+    commands.entity(trigger.entity()).insert((
+        Sliceable(half_size),
+        Collider::cuboid(half_size.x, half_size.y, half_size.z),
+        Mesh3d(meshes.add(Cuboid::from_size(size))),
+        MeshMaterial3d(materials.door.clone()),
+    ));
+}
+
+fn add_switch(
+    trigger: Trigger<OnAdd, Switch>,
+    mut commands: Commands,
+    meshes: Res<EntityMeshes>,
+    materials: Res<EntityMaterials>,
+) {
+    commands.entity(trigger.entity()).insert((
+        Mesh3d(meshes.small.clone()),
+        MeshMaterial3d(materials.switch.clone()),
+    ));
+}
+
+fn add_key(
+    trigger: Trigger<OnAdd, Key>,
+    mut commands: Commands,
+    meshes: Res<EntityMeshes>,
+    materials: Res<EntityMaterials>,
+) {
+    commands.entity(trigger.entity()).insert((
+        Mesh3d(meshes.small.clone()),
+        MeshMaterial3d(materials.key.clone()),
+    ));
+}
+
+fn add_moving_platform(
+    trigger: Trigger<OnAdd, MovingPlatform>,
+    mut commands: Commands,
+    meshes: Res<EntityMeshes>,
+    materials: Res<EntityMaterials>,
+) {
+    commands.entity(trigger.entity()).insert((
+        Mesh3d(meshes.cube.clone()),
+        MeshMaterial3d(materials.moving_platform.clone()),
+    ));
+}
+
+/// Opens and closes the doors of the switch the player is in, leaving the ones the player is
+/// inside open
+fn press_switches(
+    actions: Res<Actions>,
+    player: Single<&Transform, With<Player>>,
+    switches: Query<(&Transform, &Switch), Without<Player>>,
+    mut doors: Query<(&mut Door, &Transform), Without<Player>>,
+) {
+    if !actions.just_pressed(Action::Enter) {
+        return;
+    }
+
+    let cell = block_map::cell(player.translation);
+    let player_half_size = Vec3::splat(block::SIZE / 2.0);
+
+    for (_transform, switch) in switches
+        .iter()
+        .filter(|(transform, _switch)| block_map::cell(transform.translation) == cell)
+    {
+        for (mut door, transform) in doors
+            .iter_mut()
+            .filter(|(door, _transform)| door.id == switch.door)
+        {
+            if door.open
+                && overlaps(
+                    player.translation,
+                    player_half_size,
+                    transform.translation,
+                    door.size / 2.0,
+                )
+            {
+                continue;
+            }
+
+            door.open = !door.open;
+        }
+    }
+}
+
+fn collect_keys(
+    mut commands: Commands,
+    mut progress: ResMut<Progress>,
+    player: Single<&Transform, With<Player>>,
+    keys: Query<(Entity, &Transform, &Key), Without<Player>>,
+) {
+    let cell = block_map::cell(player.translation);
+
+    for (entity, transform, key) in &keys {
+        if block_map::cell(transform.translation) != cell {
+            continue;
+        }
+
+        progress.items.insert(key.0.clone());
+        remove_collected::<Key>(&mut commands, entity);
+    }
+}
+
+/// Hides a collected entity and saves the game
+///
+/// Only its `T` goes, so it is kept with its [`Definition`] for the editor to save.
+pub fn remove_collected<T: Component>(commands: &mut Commands, entity: Entity) {
+    commands.entity(entity).remove::<(T, Mesh3d)>();
+    commands.trigger(Save);
+}
+
+/// Opens the locked doors the player touched in the last physics step with their key
+fn unlock_doors(
+    progress: Res<Progress>,
+    output: Option<Single<&KinematicCharacterControllerOutput, With<Player>>>,
+    mut doors: Query<&mut Door>,
+) {
+    let Some(output) = output else {
+        return;
+    };
+
+    for collision in &output.collisions {
+        let Ok(mut door) = doors.get_mut(collision.entity) else {
+            continue;
+        };
+
+        if door
+            .key
+            .as_ref()
+            .is_some_and(|key| progress.items.contains(key))
+        {
+            door.open = true;
+        }
+    }
+}
+
+/// Lets the player through open doors, which are drawn faded
+fn show_doors(
+    materials: Res<EntityMaterials>,
+    mut doors: Query<
+        (
+            &Door,
+            &mut CollisionGroups,
+            &mut MeshMaterial3d<StandardMaterial>,
+        ),
+        Changed<Door>,
+    >,
+) {
+    for (door, mut collision_groups, mut material) in &mut doors {
+        if door.open {
+            *collision_groups = CollisionGroups::new(Group::NONE, Group::NONE);
+            material.0 = materials.open_door.clone();
+        } else {
+            *collision_groups = solid();
+            material.0 = materials.door.clone();
+        }
+    }
+}
+
+/// How far below the player a platform still carries them
+const CARRY_SKIN: f32 = 0.05;
+
+/// Moves the platforms along their waypoints, carrying the player riding them and pushing the
+/// player in their way, through the character controller so blocks still stop the player
+fn move_platforms(
+    time: Res<Time>,
+    player: Single<(&Transform, &mut KinematicCharacterController), With<Player>>,
+    mut platforms: Query<(&mut MovingPlatform, &mut Transform), Without<Player>>,
+) {
+    let (player_transform, mut controller) = player.into_inner();
+    let player_half_size = Vec3::splat(block::SIZE / 2.0);
+    let platform_half_size = Vec3::splat(block::SIZE / 2.0);
+    let mut carried = Vec3::ZERO;
+
+    for (mut platform, mut transform) in &mut platforms {
+        let Some(&target) = platform.waypoints.get(platform.next) else {
+            continue;
+        };
+
+        let previous = transform.translation;
+        transform.translation = previous.move_towards(target, platform.speed * time.delta_secs());
+
+        if transform.translation == target {
+            platform.next = (platform.next + 1) % platform.waypoints.len();
+        }
+
+        let riding = overlaps(
+            player_transform.translation - controller.up * CARRY_SKIN,
+            player_half_size,
+            previous,
+            platform_half_size,
+        );
+
+        let pushed = overlaps(
+            player_transform.translation,
+            player_half_size,
+            transform.translation,
+            platform_half_size,
+        );
+
+        if riding || pushed {
+            carried += transform.translation - previous;
+        }
+    }
+
+    if carried != Vec3::ZERO {
+        controller.translation = Some(controller.translation.unwrap_or_default() + carried);
+    }
+}
+
+/// Whether two boxes, from their centers and half sizes, overlap
+fn overlaps(a: Vec3, a_half_size: Vec3, b: Vec3, b_half_size: Vec3) -> bool {
+    ((a - b).abs() - (a_half_size + b_half_size)).max_element() < 0.0
+}
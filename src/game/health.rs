@@ -0,0 +1,160 @@
+//! Player health, lost to hazard blocks and all at once by falling out of the room.
+
+use super::block::BlockFlags;
+use super::checkpoints::Respawning;
+use super::loading::{FallBounds, KillPlanes};
+use super::player::Player;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+#[derive(Component, Debug)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+const MAX_HEALTH: u32 = 3;
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            current: MAX_HEALTH,
+            max: MAX_HEALTH,
+        }
+    }
+}
+
+/// Immune to damage and blinking for a while after getting hurt
+#[derive(Component)]
+pub struct Invulnerable(Timer);
+
+const INVULNERABILITY_DURATION: f32 = 1.0;
+/// Times per second the player blinks while invulnerable
+const BLINK_FREQUENCY: f32 = 10.0;
+
+impl Default for Invulnerable {
+    fn default() -> Self {
+        Self(Timer::from_seconds(
+            INVULNERABILITY_DURATION,
+            TimerMode::Once,
+        ))
+    }
+}
+
+/// The player ran out of health
+#[derive(Event)]
+pub struct Died;
+
+const HAZARD_DAMAGE: u32 = 1;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        ((hurt, fall, die).chain(), blink).run_if(in_state(super::State::Playing)),
+    );
+}
+
+/// Damages the player for touching hazard blocks in the last physics step
+fn hurt(
+    mut commands: Commands,
+    player: Single<
+        (
+            Entity,
+            &mut Health,
+            Option<&KinematicCharacterControllerOutput>,
+            Has<Invulnerable>,
+            Has<Respawning>,
+        ),
+        With<Player>,
+    >,
+    blocks: Query<&BlockFlags>,
+) {
+    let (entity, mut health, output, invulnerable, respawning) = player.into_inner();
+
+    if invulnerable || respawning {
+        return;
+    }
+
+    let touching_hazard = output.is_some_and(|output| {
+        output
+            .collisions
+            .iter()
+            .any(|collision| blocks.get(collision.entity).is_ok_and(|flags| flags.hazard))
+    });
+
+    if !touching_hazard {
+        return;
+    }
+
+    health.current = health.current.saturating_sub(HAZARD_DAMAGE);
+
+    if health.current > 0 {
+        commands.entity(entity).insert(Invulnerable::default());
+    }
+}
+
+/// How far outside the [`FallBounds`] the player gets before dying, in blocks
+const FALL_MARGIN: f32 = 4.0;
+
+/// Kills the player once outside the room's [`FallBounds`] or behind any of its [`KillPlanes`],
+/// instead of falling forever
+fn fall(
+    fall_bounds: Res<FallBounds>,
+    kill_planes: Res<KillPlanes>,
+    player: Single<(&Transform, &mut Health, Has<Respawning>), With<Player>>,
+) {
+    let (transform, mut health, respawning) = player.into_inner();
+
+    if respawning {
+        return;
+    }
+
+    let translation = transform.translation;
+
+    // Whichever way the plane is rotated, falling takes the player out of the bounds
+    let outside = fall_bounds.0.as_ref().is_some_and(|bounds| {
+        let min = bounds.min.as_vec3() - FALL_MARGIN;
+        let max = (bounds.max + 1).as_vec3() + FALL_MARGIN;
+        translation.cmplt(min).any() || translation.cmpgt(max).any()
+    });
+
+    let behind = kill_planes
+        .0
+        .iter()
+        .any(|kill_plane| (translation - kill_plane.origin).dot(kill_plane.normal) < 0.0);
+
+    if outside || behind {
+        health.current = 0;
+    }
+}
+
+/// Triggers [`Died`] once, however the health ran out
+fn die(mut commands: Commands, player: Single<(&Health, Has<Respawning>), With<Player>>) {
+    let (health, respawning) = player.into_inner();
+
+    if health.current == 0 && !respawning {
+        commands.trigger(Died);
+    }
+}
+
+fn blink(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut players: Query<(Entity, &mut Invulnerable, &mut Visibility), With<Player>>,
+) {
+    for (entity, mut invulnerable, mut visibility) in &mut players {
+        if invulnerable.0.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<Invulnerable>();
+            *visibility = Visibility::Inherited;
+            continue;
+        }
+
+        let shown = (invulnerable.0.elapsed_secs() * BLINK_FREQUENCY) as u32 % 2 == 0;
+
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
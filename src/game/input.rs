@@ -0,0 +1,261 @@
+//! Actions bound to keys and gamepad buttons, rebindable from `~/.untifted/input.json`.
+
+use super::save;
+use bevy::input::gamepad::{GamepadConnection, GamepadConnectionEvent};
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+const FILE: &str = "input.json";
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Jump,
+    Dash,
+    RotateX,
+    RotateY,
+    RotateZ,
+    CounterRotateX,
+    CounterRotateY,
+    CounterRotateZ,
+    UndoRotation,
+    /// Move to the slice behind the plane
+    DepthShiftIn,
+    /// Move to the slice in front of the plane
+    DepthShiftOut,
+    /// Go through a door
+    Enter,
+    ToggleGhosts,
+    ToggleFullscreen,
+    Save,
+    EditorSave,
+    EditorSaveBinary,
+    EditorNextBlockKind,
+    /// Cycle the kind of placed entities, then back to blocks
+    EditorNextEntityKind,
+    /// Cycle the ability, speed, spawn point or door of the placed entities
+    EditorNextEntityOption,
+    /// Held to paint every connected block of the same kind, or to extend the last placed door or
+    /// platform
+    EditorFill,
+}
+
+/// Keys that must all be held at once
+pub type Chord = Vec<KeyCode>;
+
+/// The bindings of every action, any of which triggers it
+#[derive(Resource, Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct InputMap {
+    pub keyboard: HashMap<Action, Vec<Chord>>,
+    pub gamepad: HashMap<Action, Vec<GamepadButton>>,
+    /// How far the left stick has to be pushed to move, from 0 to 1
+    pub deadzone: f32,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use Action::*;
+        use KeyCode::*;
+
+        Self {
+            keyboard: HashMap::from([
+                (MoveLeft, vec![vec![KeyA]]),
+                (MoveRight, vec![vec![KeyD]]),
+                (MoveUp, vec![vec![KeyW]]),
+                (MoveDown, vec![vec![KeyS]]),
+                (Jump, vec![vec![Space]]),
+                (Dash, vec![vec![KeyF]]),
+                (RotateX, vec![vec![Digit1]]),
+                (RotateY, vec![vec![Digit2]]),
+                (RotateZ, vec![vec![Digit3]]),
+                (CounterRotateX, vec![vec![ShiftLeft, Digit1]]),
+                (CounterRotateY, vec![vec![ShiftLeft, Digit2]]),
+                (CounterRotateZ, vec![vec![ShiftLeft, Digit3]]),
+                (UndoRotation, vec![vec![Backspace]]),
+                (DepthShiftIn, vec![vec![KeyE]]),
+                (DepthShiftOut, vec![vec![KeyQ]]),
+                (Enter, vec![vec![KeyR]]),
+                (ToggleGhosts, vec![vec![KeyG]]),
+                (ToggleFullscreen, vec![vec![F11]]),
+                (Save, vec![vec![F5]]),
+                (EditorSave, vec![vec![ControlLeft, KeyM]]),
+                (EditorSaveBinary, vec![vec![ControlLeft, ShiftLeft, KeyM]]),
+                (EditorNextBlockKind, vec![vec![Tab]]),
+                (EditorNextEntityKind, vec![vec![KeyN]]),
+                (EditorNextEntityOption, vec![vec![KeyO]]),
+                (EditorFill, vec![vec![ShiftLeft]]),
+            ]),
+            gamepad: HashMap::from([
+                (MoveLeft, vec![GamepadButton::DPadLeft]),
+                (MoveRight, vec![GamepadButton::DPadRight]),
+                (MoveUp, vec![GamepadButton::DPadUp]),
+                (MoveDown, vec![GamepadButton::DPadDown]),
+                (Jump, vec![GamepadButton::South]),
+                (Dash, vec![GamepadButton::East]),
+                (RotateX, vec![GamepadButton::LeftTrigger]),
+                (RotateY, vec![GamepadButton::RightTrigger]),
+                (RotateZ, vec![GamepadButton::North]),
+                (CounterRotateX, vec![GamepadButton::LeftTrigger2]),
+                (CounterRotateY, vec![GamepadButton::RightTrigger2]),
+                (UndoRotation, vec![GamepadButton::Select]),
+                (DepthShiftIn, vec![GamepadButton::RightThumb]),
+                (DepthShiftOut, vec![GamepadButton::LeftThumb]),
+                (Enter, vec![GamepadButton::West]),
+                (Save, vec![GamepadButton::Start]),
+            ]),
+            deadzone: 0.2,
+        }
+    }
+}
+
+impl InputMap {
+    /// Reads the bindings from the config file, keeping the defaults of actions it leaves out
+    fn load() -> Self {
+        let mut input_map = Self::default();
+
+        let Some(path) = save::directory().map(|directory| directory.join(FILE)) else {
+            return input_map;
+        };
+
+        let Ok(json) = fs::read(&path) else {
+            return input_map;
+        };
+
+        match serde_json::from_slice::<Self>(&json) {
+            Ok(config) => {
+                input_map.keyboard.extend(config.keyboard);
+                input_map.gamepad.extend(config.gamepad);
+                // Scaling the stick divides by what is left past the deadzone
+                input_map.deadzone = config.deadzone.clamp(0.0, 0.99);
+            }
+            Err(error) => error!("Error reading {}: {error}", path.display()),
+        }
+
+        input_map
+    }
+}
+
+/// The actions held and started this frame
+#[derive(Resource, Default, Debug)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    /// The left stick past the deadzone
+    stick: Vec2,
+}
+
+impl Actions {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// Every action held
+    pub fn held(&self) -> &HashSet<Action> {
+        &self.pressed
+    }
+
+    /// -1, 0 or 1 depending on which of the two actions are held
+    pub fn axis(&self, negative: Action, positive: Action) -> f32 {
+        self.pressed(positive) as i8 as f32 - self.pressed(negative) as i8 as f32
+    }
+
+    /// The direction to move in, from the move actions or else the left stick
+    pub fn movement(&self) -> Vec2 {
+        let digital = Vec2::new(
+            self.axis(Action::MoveLeft, Action::MoveRight),
+            self.axis(Action::MoveDown, Action::MoveUp),
+        );
+
+        if digital != Vec2::ZERO {
+            digital.normalize()
+        } else {
+            self.stick
+        }
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.insert_resource(InputMap::load())
+        .init_resource::<Actions>()
+        .add_systems(PreUpdate, update.after(InputSystem))
+        .add_systems(
+            Update,
+            log_gamepad_connections.run_if(in_state(super::State::Playing)),
+        );
+}
+
+fn update(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    input_map: Res<InputMap>,
+    mut actions: ResMut<Actions>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    actions.stick = Vec2::ZERO;
+
+    for (&action, chords) in &input_map.keyboard {
+        for chord in chords {
+            if chord.is_empty() || !keyboard.all_pressed(chord.iter().copied()) {
+                continue;
+            }
+
+            actions.pressed.insert(action);
+
+            if keyboard.any_just_pressed(chord.iter().copied()) {
+                actions.just_pressed.insert(action);
+            }
+        }
+    }
+
+    // Gamepads are entities, so controllers plugged in or out mid-game are picked up here
+    for gamepad in &gamepads {
+        for (&action, buttons) in &input_map.gamepad {
+            if gamepad.any_pressed(buttons.iter().copied()) {
+                actions.pressed.insert(action);
+            }
+
+            if gamepad.any_just_pressed(buttons.iter().copied()) {
+                actions.just_pressed.insert(action);
+            }
+        }
+
+        let stick = deadzone(gamepad.left_stick(), input_map.deadzone);
+
+        if stick.length() > actions.stick.length() {
+            actions.stick = stick;
+        }
+    }
+}
+
+/// Zeroes `stick` inside the deadzone and rescales the rest to still reach full speed
+fn deadzone(stick: Vec2, deadzone: f32) -> Vec2 {
+    let length = stick.length();
+
+    if length <= deadzone {
+        return Vec2::ZERO;
+    }
+
+    let scaled_length = ((length - deadzone) / (1.0 - deadzone)).min(1.0);
+    stick / length * scaled_length
+}
+
+fn log_gamepad_connections(mut events: EventReader<GamepadConnectionEvent>) {
+    for event in events.read() {
+        match &event.connection {
+            GamepadConnection::Connected { name, .. } => info!("Gamepad connected: {name}"),
+            GamepadConnection::Disconnected => info!("Gamepad disconnected"),
+        }
+    }
+}
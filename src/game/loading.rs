@@ -1,22 +1,35 @@
+pub mod binary_level_loader;
+pub mod block_kinds_loader;
 pub mod game_loader;
 pub mod level_loader;
+pub mod world_loader;
 
-use super::block::{BlockBundle, TILE_SIZE};
+use super::abilities::Pickup;
+use super::block::{BlockBundle, ONE_WAY, SOLID, TILE_SIZE};
+use super::block_map;
 use super::camera::GameCamera;
-use super::plane::Rotate;
+use super::checkpoints::Checkpoint;
+use super::entities::{Definition, Door, Key, MovingPlatform, SpawnPoint, Switch};
+use super::plane::{Orientation, Plane, Rotate, Rotation};
 use super::player::Player;
+use super::save::{Progress, Slot};
+use crate::systems::despawn_recursive;
 use bevy::asset::LoadState;
 use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use block_kinds_loader::BlockKinds;
 use game_loader::Game;
-use level_loader::Level;
-use std::env;
+use level_loader::{Bounds, KillPlane, Level, LevelEntity};
 use std::path::PathBuf;
+use world_loader::{WorldMap, START_ROOM, START_SPAWN_POINT};
 
 #[derive(SubStates, Default, Debug, Clone, PartialEq, Eq, Hash)]
 #[source(super::State = super::State::Loading)]
 enum State {
     #[default]
     Game,
+    BlockKinds,
+    World,
     Level,
     Spawn,
 }
@@ -70,89 +83,323 @@ impl FromWorld for BlockMaterial {
     }
 }
 
+/// Faded silhouettes of the blocks in the slices next to the plane
 #[derive(Resource)]
-pub struct LoadingGame(pub Handle<Game>);
+pub struct GhostMaterial(pub Handle<StandardMaterial>);
+
+const GHOST_COLOR: Color = Color::srgb(0.6, 0.7, 1.0);
+
+impl FromWorld for GhostMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let material = StandardMaterial {
+            base_color: GHOST_COLOR,
+            unlit: true,
+            cull_mode: None,
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        };
 
-const GAME_DIRECTORY: &str = ".untifted";
-const GAME_FILE: &str = "game.json";
+        Self(materials.add(material))
+    }
+}
+
+#[derive(Resource)]
+pub struct LoadingGame(pub Handle<Game>);
 
 impl FromWorld for LoadingGame {
     fn from_world(world: &mut World) -> Self {
         let asset_server = world.resource::<AssetServer>();
-        let home_directory = env::var("HOME").unwrap();
-        let path = PathBuf::from(format!("{home_directory}/{GAME_DIRECTORY}/{GAME_FILE}"));
-        Self(asset_server.load(path))
+
+        // Nothing to read without a save directory, which starts a new game
+        match world.resource::<Slot>().path() {
+            Some(path) => Self(asset_server.load(path)),
+            None => Self(Handle::default()),
+        }
     }
 }
 
 #[derive(Resource)]
-struct LoadingLevel(Handle<Level>);
+pub struct LoadingBlockKinds(pub Handle<BlockKinds>);
 
-pub const LEVELS_DIRECTORY: &str = "levels";
+const BLOCK_KINDS_FILE: &str = "blocks.json";
 
-impl FromWorld for LoadingLevel {
+impl FromWorld for LoadingBlockKinds {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        Self(asset_server.load(BLOCK_KINDS_FILE))
+    }
+}
+
+/// The room being played and the spawn point the player entered it from
+#[derive(Resource, Clone, Debug)]
+pub struct Location {
+    pub room: String,
+    pub spawn_point: String,
+    /// Where a saved game left the player, instead of the spawn point
+    pub translation: Option<Vec3>,
+}
+
+impl Location {
+    fn start() -> Self {
+        Self {
+            room: START_ROOM.into(),
+            spawn_point: START_SPAWN_POINT.into(),
+            translation: None,
+        }
+    }
+}
+
+impl FromWorld for Location {
+    fn from_world(world: &mut World) -> Self {
+        let games = world.resource::<Assets<Game>>();
+        let game = games.get(&world.resource::<LoadingGame>().0).unwrap();
+
+        Self {
+            room: game.room.clone(),
+            spawn_point: game.spawn_point.clone(),
+            translation: game.translation,
+        }
+    }
+}
+
+/// The bounds of the room being played, from its level
+#[derive(Resource, Default)]
+pub struct RoomBounds(pub Option<Bounds>);
+
+/// The kill planes of the room being played, from its level
+#[derive(Resource, Default)]
+pub struct KillPlanes(pub Vec<KillPlane>);
+
+/// The cells the player falls to their death some way outside of, in whichever direction is down:
+/// the room's bounds, or else the cells its blocks span
+#[derive(Resource, Default)]
+pub struct FallBounds(pub Option<Bounds>);
+
+#[derive(Resource)]
+pub struct LoadingWorld(pub Handle<WorldMap>);
+
+pub const WORLDS_DIRECTORY: &str = "worlds";
+
+impl FromWorld for LoadingWorld {
     fn from_world(world: &mut World) -> Self {
         let games = world.resource::<Assets<Game>>();
         let game = games.get(&world.resource::<LoadingGame>().0).unwrap();
         let asset_server = world.resource::<AssetServer>();
-        let file = format!("{level}.json", level = game.level);
-        let path = PathBuf::from(format!("{LEVELS_DIRECTORY}/{file}"));
+        let file = format!("{world}.json", world = game.world);
+        let path = PathBuf::from(format!("{WORLDS_DIRECTORY}/{file}"));
         Self(asset_server.load(path))
     }
 }
 
+#[derive(Resource)]
+struct LoadingLevel(Handle<Level>);
+
+pub const LEVELS_DIRECTORY: &str = "levels";
+
+/// Everything despawned when leaving the room
+#[derive(Component, Default)]
+pub struct RoomEntity;
+
 pub fn plugin(app: &mut App) {
-    app.add_plugins((game_loader::plugin, level_loader::plugin))
-        .add_sub_state::<State>()
-        .init_resource::<MyTextureAtlasLayout>()
-        .init_resource::<TextureAtlasImage>()
-        .init_resource::<BlockMaterial>()
-        .add_systems(OnEnter(State::Game), load_game)
-        .add_systems(Update, await_game.run_if(in_state(State::Game)))
-        .add_systems(OnEnter(State::Level), load_level)
-        .add_systems(Update, await_level.run_if(in_state(State::Level)))
-        .add_systems(OnEnter(State::Spawn), spawn);
+    app.add_plugins((
+        game_loader::plugin,
+        block_kinds_loader::plugin,
+        world_loader::plugin,
+        level_loader::plugin,
+        binary_level_loader::plugin,
+    ))
+    .add_sub_state::<State>()
+    .init_resource::<MyTextureAtlasLayout>()
+    .init_resource::<TextureAtlasImage>()
+    .init_resource::<BlockMaterial>()
+    .init_resource::<GhostMaterial>()
+    .init_resource::<RoomBounds>()
+    .init_resource::<KillPlanes>()
+    .init_resource::<FallBounds>()
+    .add_systems(OnEnter(State::Game), load_game)
+    .add_systems(Update, await_game.run_if(in_state(State::Game)))
+    .add_systems(OnEnter(State::BlockKinds), load_block_kinds)
+    .add_systems(
+        Update,
+        await_block_kinds.run_if(in_state(State::BlockKinds)),
+    )
+    .add_systems(OnEnter(State::World), load_world)
+    .add_systems(Update, await_world.run_if(in_state(State::World)))
+    .add_systems(OnEnter(State::Level), load_level)
+    .add_systems(Update, await_level.run_if(in_state(State::Level)))
+    .add_systems(OnEnter(State::Spawn), spawn)
+    .add_systems(
+        OnExit(super::State::Playing),
+        despawn_recursive::<With<RoomEntity>>,
+    );
 }
 
 fn load_game(mut commands: Commands) {
     commands.init_resource::<LoadingGame>();
 }
 
+/// Marks the saved game as restored, so entering later rooms keeps the progress made since
+#[derive(Resource)]
+struct GameRestored;
+
 fn await_game(
+    mut commands: Commands,
     asset_server: Res<AssetServer>,
+    restored: Option<Res<GameRestored>>,
     mut loading_game: ResMut<LoadingGame>,
     mut games: ResMut<Assets<Game>>,
+    mut rotation: ResMut<Rotation>,
     mut next_state: ResMut<NextState<State>>,
 ) {
-    let load_state = asset_server.get_load_state(loading_game.0.id()).unwrap();
+    if restored.is_some() {
+        next_state.set(State::BlockKinds);
+        return;
+    }
 
-    match &load_state {
-        LoadState::Loaded => {
-            next_state.set(State::Level);
+    if !games.contains(loading_game.0.id()) {
+        match asset_server.get_load_state(loading_game.0.id()) {
+            // Not loaded at all without a save directory
+            Some(LoadState::Failed(_)) | None => {
+                loading_game.0 = games.add(Game::default());
+            }
+
+            _ => return,
         }
+    }
+
+    let game = games.get(loading_game.0.id()).unwrap();
+
+    *rotation = Rotation::new(
+        game.rotation
+            .map_or(game.orientation, Orientation::from_quat),
+    );
+    commands.insert_resource(Progress {
+        abilities: game.abilities.clone(),
+        items: game.items.clone(),
+    });
+
+    commands.insert_resource(GameRestored);
+    next_state.set(State::BlockKinds);
+}
+
+fn load_block_kinds(mut commands: Commands) {
+    commands.init_resource::<LoadingBlockKinds>();
+}
+
+fn await_block_kinds(
+    asset_server: Res<AssetServer>,
+    loading_block_kinds: Res<LoadingBlockKinds>,
+    mut next_state: ResMut<NextState<State>>,
+) {
+    let load_state = asset_server
+        .get_load_state(loading_block_kinds.0.id())
+        .unwrap();
+
+    if load_state.is_loaded() {
+        next_state.set(State::World);
+    }
+}
+
+fn load_world(mut commands: Commands) {
+    commands.init_resource::<LoadingWorld>();
+    commands.init_resource::<Location>();
+}
+
+fn await_world(
+    asset_server: Res<AssetServer>,
+    loading_world: Res<LoadingWorld>,
+    worlds: Res<Assets<WorldMap>>,
+    mut location: ResMut<Location>,
+    mut next_state: ResMut<NextState<State>>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    let load_state = asset_server.get_load_state(loading_world.0.id()).unwrap();
 
-        LoadState::Failed(_error) => {
-            loading_game.0 = games.add(Game::default());
-            next_state.set(State::Level);
+    match &load_state {
+        LoadState::Loaded => {}
+
+        // There is no other world to fall back to
+        LoadState::Failed(error) => {
+            error!("Error loading world: {error}");
+            app_exit.send(AppExit::error());
+            return;
         }
 
-        _ => (),
+        _ => return,
+    }
+
+    let world_map = worlds.get(loading_world.0.id()).unwrap();
+
+    if !world_map.rooms.contains_key(&location.room) {
+        error!("Unknown room: {}", location.room);
+        restart(&mut location, &mut app_exit);
+        return;
+    }
+
+    next_state.set(State::Level);
+}
+
+/// Sends the player back to the start of the world after their location turned out broken, or
+/// quits if it already was the start
+fn restart(location: &mut Location, app_exit: &mut EventWriter<AppExit>) {
+    if location.room == START_ROOM && location.spawn_point == START_SPAWN_POINT {
+        app_exit.send(AppExit::error());
+        return;
     }
+
+    *location = Location::start();
 }
 
-fn load_level(mut commands: Commands) {
-    commands.init_resource::<LoadingLevel>();
+fn load_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    worlds: Res<Assets<WorldMap>>,
+    loading_world: Res<LoadingWorld>,
+    location: Res<Location>,
+) {
+    // Checked by `await_world`
+    let Some(room) = worlds
+        .get(loading_world.0.id())
+        .and_then(|world_map| world_map.rooms.get(&location.room))
+    else {
+        return;
+    };
+
+    let mut path = PathBuf::from(format!("{LEVELS_DIRECTORY}/{level}", level = room.level));
+
+    // The extension picks the loader, JSON if there is none
+    if path.extension().is_none() {
+        path.set_extension("json");
+    }
+
+    commands.insert_resource(LoadingLevel(asset_server.load(path)));
 }
 
 fn await_level(
     asset_server: Res<AssetServer>,
-    loading_level: Res<LoadingLevel>,
+    loading_level: Option<Res<LoadingLevel>>,
+    mut location: ResMut<Location>,
     mut next_state: ResMut<NextState<State>>,
+    mut app_exit: EventWriter<AppExit>,
 ) {
+    let Some(loading_level) = loading_level else {
+        return;
+    };
+
     let load_state = asset_server.get_load_state(loading_level.0.id()).unwrap();
 
-    if load_state.is_loaded() {
-        next_state.set(State::Spawn);
+    match &load_state {
+        LoadState::Loaded => next_state.set(State::Spawn),
+
+        // Falls back to the start room, checked again with the world
+        LoadState::Failed(error) => {
+            error!("Error loading level: {error}");
+            restart(&mut location, &mut app_exit);
+            next_state.set(State::World);
+        }
+
+        _ => {}
     }
 }
 
@@ -160,30 +407,217 @@ fn spawn(
     mut commands: Commands,
     levels: Res<Assets<Level>>,
     loading_level: Res<LoadingLevel>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    layouts: Res<Assets<TextureAtlasLayout>>,
-    layout: Res<MyTextureAtlasLayout>,
-    material: Res<BlockMaterial>,
+    block_kinds: Res<Assets<BlockKinds>>,
+    loading_block_kinds: Res<LoadingBlockKinds>,
+    worlds: Res<Assets<WorldMap>>,
+    loading_world: Res<LoadingWorld>,
+    mut location: ResMut<Location>,
+    rotation: Res<Rotation>,
+    progress: Res<Progress>,
+    mut plane: ResMut<Plane>,
+    mut next_loading_state: ResMut<NextState<State>>,
     mut next_state: ResMut<NextState<super::State>>,
+    mut app_exit: EventWriter<AppExit>,
 ) {
+    let level = levels.get(loading_level.0.id()).unwrap();
+    let block_kinds = block_kinds.get(loading_block_kinds.0.id()).unwrap();
+    let room = worlds
+        .get(loading_world.0.id())
+        .and_then(|world_map| world_map.rooms.get(&location.room));
+
+    // Spawn points placed in the level come before the ones of the room
+    let translation = location
+        .translation
+        .or_else(|| {
+            level.entities.iter().find_map(|entity| match entity {
+                LevelEntity::SpawnPoint { name, cell } if *name == location.spawn_point => {
+                    Some(cell.as_vec3() + 0.5)
+                }
+                _ => None,
+            })
+        })
+        .or_else(|| room?.spawn_points.get(&location.spawn_point).copied());
+
+    let Some(translation) = translation else {
+        error!(
+            "Unknown spawn point {} in room {}",
+            location.spawn_point, location.room
+        );
+        restart(&mut location, &mut app_exit);
+        next_loading_state.set(State::World);
+        return;
+    };
+
+    // Starts on the player, so the camera doesn't sweep in from the last room
     commands
-        .spawn((Name::new("Camera plane rotation"), Rotate))
+        .spawn((
+            Name::new("Camera plane rotation"),
+            Rotate,
+            RoomEntity,
+            Transform::from_translation(translation).with_rotation(rotation.get()),
+        ))
         .with_child(GameCamera);
 
-    commands.spawn((Player, Transform::from_xyz(0.0, 10.0, 0.5)));
+    commands.spawn((
+        Player,
+        RoomEntity,
+        Transform::from_translation(translation),
+        KinematicCharacterController {
+            up: rotation.get() * Vec3::Y,
+            filter_groups: Some(CollisionGroups::new(Group::ALL, SOLID | ONE_WAY)),
+            ..default()
+        },
+    ));
 
-    let level = levels.get(loading_level.0.id()).unwrap();
+    commands.insert_resource(RoomBounds(level.bounds.clone()));
+    commands.insert_resource(KillPlanes(level.kill_planes.clone()));
+
+    // Wherever the player enters counts as inside, even above the blocks
+    let fall_bounds = level.bounds.clone().or_else(|| level.extent());
+    let spawn_cell = block_map::cell(translation);
+    commands.insert_resource(FallBounds(
+        fall_bounds.map(|bounds| bounds.including(spawn_cell)),
+    ));
 
     for block in &level.blocks {
-        commands.spawn(BlockBundle::new(
-            &block.translation,
-            &mut meshes,
-            &layouts,
-            layout.0.clone(),
-            block.texture_atlas_indices.clone(),
-            material.0.clone(),
+        let Some(kind) = block_kinds.0.get(&block.kind) else {
+            warn!("Unknown block kind: {}", block.kind);
+            continue;
+        };
+
+        commands.spawn((
+            BlockBundle::new(
+                &block.translation,
+                &block.kind,
+                kind,
+                block.texture_atlas_indices.clone(),
+            ),
+            RoomEntity,
         ));
     }
 
+    for entity in &level.entities {
+        spawn_entity(&mut commands, entity, &progress);
+    }
+
+    // Slice the new blocks even if the plane ends up where it was
+    plane.set_changed();
+
     next_state.set(super::State::Playing);
 }
+
+/// Spawns what `definition` places in the level
+pub fn spawn_entity(
+    commands: &mut Commands,
+    definition: &LevelEntity,
+    progress: &Progress,
+) -> Entity {
+    let mut entity = commands.spawn((Definition(definition.clone()), RoomEntity));
+
+    match definition {
+        LevelEntity::Door { id, min, max, key } => {
+            entity.insert(Door::new(id, key.clone(), *min, *max));
+        }
+
+        LevelEntity::Switch { cell, door } => {
+            entity.insert((
+                Switch { door: door.clone() },
+                Transform::from_translation(cell.as_vec3() + 0.5),
+            ));
+        }
+
+        LevelEntity::Key { cell, id } => {
+            entity.insert(Transform::from_translation(cell.as_vec3() + 0.5));
+
+            // Keys and pickups already collected are gone for good
+            if !progress.items.contains(id) {
+                entity.insert(Key(id.clone()));
+            }
+        }
+
+        LevelEntity::MovingPlatform { waypoints, speed } => {
+            entity.insert(MovingPlatform::new(waypoints, *speed));
+        }
+
+        LevelEntity::SpawnPoint { name, cell } => {
+            entity.insert((
+                SpawnPoint(name.clone()),
+                Transform::from_translation(cell.as_vec3() + 0.5),
+            ));
+        }
+
+        LevelEntity::Pickup { cell, ability } => {
+            entity.insert(Transform::from_translation(cell.as_vec3() + 0.5));
+
+            if !progress.abilities.contains(ability) {
+                entity.insert(Pickup(*ability));
+            }
+        }
+
+        LevelEntity::Checkpoint { cell } => {
+            entity.insert((
+                Checkpoint,
+                Transform::from_translation(cell.as_vec3() + 0.5),
+            ));
+        }
+    }
+
+    entity.id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::abilities::Ability;
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::collections::HashSet;
+
+    fn app(game: Game) -> App {
+        let mut app = App::new();
+
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Game>()
+            .init_resource::<Rotation>()
+            .init_resource::<Progress>()
+            .init_resource::<NextState<State>>();
+
+        let handle = app.world_mut().resource_mut::<Assets<Game>>().add(game);
+        app.insert_resource(LoadingGame(handle));
+        app
+    }
+
+    #[test]
+    fn restores_saved_game() {
+        let orientation = Orientation::default().turned(IVec3::X, true);
+
+        let mut app = app(Game {
+            orientation,
+            abilities: HashSet::from([Ability::Dash]),
+            items: HashSet::from(["key".into()]),
+            ..default()
+        });
+
+        app.world_mut().run_system_once(await_game).unwrap();
+
+        let progress = app.world().resource::<Progress>();
+        assert_eq!(progress.abilities, HashSet::from([Ability::Dash]));
+        assert!(progress.items.contains("key"));
+        assert_eq!(app.world().resource::<Rotation>().target(), orientation);
+    }
+
+    #[test]
+    fn keeps_progress_in_later_rooms() {
+        let mut app = app(Game::default());
+
+        app.world_mut().run_system_once(await_game).unwrap();
+
+        app.world_mut()
+            .resource_mut::<Progress>()
+            .items
+            .insert("key".into());
+
+        app.world_mut().run_system_once(await_game).unwrap();
+
+        assert!(app.world().resource::<Progress>().items.contains("key"));
+    }
+}
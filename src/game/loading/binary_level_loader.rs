@@ -0,0 +1,363 @@
+//! A compact binary alternative to JSON levels, loaded from `.lvl` files.
+//!
+//! All numbers are little-endian:
+//!
+//! - Header: `ULVL`, level version as `u32`, flags as `u8`
+//! - Payload, zlib compressed if the [`COMPRESSED`] flag is set:
+//!   - Palette: `u32` length, then per entry the kind as `u32` length and UTF-8, and `u8` 1
+//!     followed by `u32` X, Y and Z texture atlas indices if they are overridden, else `u8` 0.
+//!     Before version 2, entries are only the texture atlas indices
+//!   - Blocks: `u32` length, then `i32` X, Y and Z cell and `u32` palette index per block
+//!   - Everything else in the level as `u32` length and JSON
+
+use super::super::block::TextureAtlasIndices;
+use super::level_loader::{migrate, Level, LevelLoaderError};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde_json::{json, Map, Value};
+use std::io;
+use std::io::{Read, Write};
+use thiserror::Error;
+
+pub const EXTENSION: &str = "lvl";
+
+const MAGIC: &[u8; 4] = b"ULVL";
+pub const COMPRESSED: u8 = 1;
+
+/// The most a compressed payload may expand to, so small files can't exhaust memory
+const MAX_PAYLOAD: u64 = 64 * 1024 * 1024;
+
+#[derive(Default)]
+struct BinaryLevelLoader;
+
+#[non_exhaustive]
+#[derive(Error, Debug)]
+enum BinaryLevelLoaderError {
+    #[error("Error reading level: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a binary level")]
+    Magic,
+    #[error("Level is cut short")]
+    Truncated,
+    #[error("Level expands to more than {MAX_PAYLOAD} bytes")]
+    TooLarge,
+    #[error(transparent)]
+    Level(#[from] LevelLoaderError),
+}
+
+impl AssetLoader for BinaryLevelLoader {
+    type Asset = Level;
+    type Settings = ();
+    type Error = BinaryLevelLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let mut level = decode(&bytes)?;
+        migrate(&mut level)?;
+        Ok(serde_json::from_value(level).map_err(LevelLoaderError::from)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[EXTENSION]
+    }
+}
+
+/// Encodes a level, with `flags` such as [`COMPRESSED`]
+pub fn encode(level: &Level, flags: u8) -> io::Result<Vec<u8>> {
+    let mut palette: Vec<(&str, Option<[u32; 3]>)> = Vec::new();
+    let mut palette_indices: HashMap<(&str, Option<[u32; 3]>), u32> = HashMap::new();
+    let mut payload = Vec::new();
+    let mut blocks = Vec::new();
+
+    for block in &level.blocks {
+        let indices = block
+            .texture_atlas_indices
+            .as_ref()
+            .map(|indices| [indices.x, indices.y, indices.z].map(|index| index as u32));
+
+        let entry = (block.kind.as_str(), indices);
+
+        let palette_index = *palette_indices.entry(entry).or_insert_with(|| {
+            palette.push(entry);
+            palette.len() as u32 - 1
+        });
+
+        for coordinate in block.translation.floor().as_ivec3().to_array() {
+            blocks.extend(coordinate.to_le_bytes());
+        }
+
+        blocks.extend(palette_index.to_le_bytes());
+    }
+
+    payload.extend((palette.len() as u32).to_le_bytes());
+
+    for (kind, indices) in palette {
+        payload.extend((kind.len() as u32).to_le_bytes());
+        payload.extend(kind.as_bytes());
+
+        let Some(indices) = indices else {
+            payload.push(0);
+            continue;
+        };
+
+        payload.push(1);
+
+        for index in indices {
+            payload.extend(index.to_le_bytes());
+        }
+    }
+
+    payload.extend((level.blocks.len() as u32).to_le_bytes());
+    payload.extend(blocks);
+
+    let mut rest = serde_json::to_value(level)?;
+
+    if let Some(rest) = rest.as_object_mut() {
+        rest.remove("version");
+        rest.remove("blocks");
+    }
+
+    let rest = serde_json::to_vec(&rest)?;
+    payload.extend((rest.len() as u32).to_le_bytes());
+    payload.extend(rest);
+
+    let mut bytes = Vec::new();
+    bytes.extend(MAGIC);
+    bytes.extend((level.version as u32).to_le_bytes());
+    bytes.push(flags);
+
+    if flags & COMPRESSED != 0 {
+        let mut encoder = ZlibEncoder::new(bytes, Compression::default());
+        encoder.write_all(&payload)?;
+        bytes = encoder.finish()?;
+    } else {
+        bytes.extend(payload);
+    }
+
+    Ok(bytes)
+}
+
+/// Decodes a level into the same document its JSON would parse to, so it can be migrated
+fn decode(bytes: &[u8]) -> Result<Value, BinaryLevelLoaderError> {
+    let mut reader = bytes;
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(BinaryLevelLoaderError::Magic);
+    }
+
+    let version = read_u32(&mut reader)?;
+    let mut flags = [0];
+    reader.read_exact(&mut flags)?;
+
+    let mut payload = Vec::new();
+
+    if flags[0] & COMPRESSED != 0 {
+        // One byte over the limit tells a payload that is too large from one that fits exactly
+        ZlibDecoder::new(reader)
+            .take(MAX_PAYLOAD + 1)
+            .read_to_end(&mut payload)?;
+
+        if payload.len() as u64 > MAX_PAYLOAD {
+            return Err(BinaryLevelLoaderError::TooLarge);
+        }
+    } else {
+        payload.extend(reader);
+    }
+
+    let mut reader = payload.as_slice();
+    let mut palette = Vec::new();
+
+    for _ in 0..read_u32(&mut reader)? {
+        let mut entry = Map::new();
+
+        let overridden = if version < 2 {
+            true
+        } else {
+            let kind = read_length_prefixed(&mut reader)?;
+
+            let kind = String::from_utf8(kind.to_vec())
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+            entry.insert("kind".into(), Value::from(kind));
+
+            let mut overridden = [0];
+            reader.read_exact(&mut overridden)?;
+            overridden[0] != 0
+        };
+
+        if overridden {
+            let indices = TextureAtlasIndices {
+                x: read_u32(&mut reader)? as usize,
+                y: read_u32(&mut reader)? as usize,
+                z: read_u32(&mut reader)? as usize,
+            };
+
+            entry.insert("texture_atlas_indices".into(), json!(indices));
+        }
+
+        palette.push(entry);
+    }
+
+    let mut blocks = Vec::new();
+
+    for _ in 0..read_u32(&mut reader)? {
+        let translation = [
+            read_i32(&mut reader)?,
+            read_i32(&mut reader)?,
+            read_i32(&mut reader)?,
+        ];
+
+        let mut block = palette
+            .get(read_u32(&mut reader)? as usize)
+            .ok_or(io::Error::from(io::ErrorKind::InvalidData))?
+            .clone();
+
+        block.insert("translation".into(), json!(translation));
+        blocks.push(Value::from(block));
+    }
+
+    let rest = read_length_prefixed(&mut reader)?;
+
+    let mut level: Map<String, Value> =
+        serde_json::from_slice(rest).map_err(LevelLoaderError::from)?;
+
+    level.insert("version".into(), Value::from(version));
+    level.insert("blocks".into(), Value::from(blocks));
+
+    Ok(Value::from(level))
+}
+
+fn read_u32(reader: &mut &[u8]) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32(reader: &mut &[u8]) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+/// Reads a `u32` length and that many bytes, checking the length against what is left first
+fn read_length_prefixed<'a>(reader: &mut &'a [u8]) -> Result<&'a [u8], BinaryLevelLoaderError> {
+    let length = read_u32(reader)? as usize;
+
+    if length > reader.len() {
+        return Err(BinaryLevelLoaderError::Truncated);
+    }
+
+    let (bytes, rest) = reader.split_at(length);
+    *reader = rest;
+    Ok(bytes)
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_asset_loader::<BinaryLevelLoader>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::level_loader::{self, Block, LevelEntity};
+    use super::*;
+
+    fn level() -> Level {
+        let block = |translation: Vec3, kind: &str, indices: Option<[usize; 3]>| Block {
+            translation,
+            kind: kind.into(),
+            texture_atlas_indices: indices.map(|[x, y, z]| TextureAtlasIndices { x, y, z }),
+        };
+
+        Level {
+            version: level_loader::VERSION,
+            bounds: None,
+            blocks: vec![
+                block(Vec3::new(0.0, 0.0, 0.0), "solid", None),
+                block(Vec3::new(1.0, 0.0, 0.0), "solid", None),
+                block(Vec3::new(-2.0, 3.0, -4.0), "solid", Some([1, 2, 3])),
+                block(Vec3::new(0.0, 5.0, 0.0), "platform", None),
+            ],
+            entities: vec![LevelEntity::Checkpoint {
+                cell: IVec3::new(1, 1, 0),
+            }],
+            kill_planes: Vec::new(),
+        }
+    }
+
+    fn round_trip(flags: u8) {
+        let level = level();
+        let bytes = encode(&level, flags).unwrap();
+
+        let mut decoded = decode(&bytes).unwrap();
+        migrate(&mut decoded).unwrap();
+        let decoded: Level = serde_json::from_value(decoded).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&level).unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trips_uncompressed() {
+        round_trip(0);
+    }
+
+    #[test]
+    fn round_trips_compressed() {
+        round_trip(COMPRESSED);
+    }
+
+    #[test]
+    fn shares_palette_entries() {
+        let uncompressed = encode(&level(), 0).unwrap();
+        let mut reader = &uncompressed[MAGIC.len() + 5..];
+
+        // Both plain solid blocks share an entry
+        assert_eq!(read_u32(&mut reader).unwrap(), 3);
+    }
+
+    #[test]
+    fn rejects_truncated_lengths() {
+        let mut bytes = encode(&level(), 0).unwrap();
+        let header = MAGIC.len() + 5;
+
+        // The first palette kind claims far more bytes than the level has
+        bytes[header + 4..header + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(matches!(
+            decode(&bytes),
+            Err(BinaryLevelLoaderError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend((level_loader::VERSION as u32).to_le_bytes());
+        bytes.push(COMPRESSED);
+
+        let mut encoder = ZlibEncoder::new(bytes, Compression::default());
+        io::copy(&mut io::repeat(0).take(MAX_PAYLOAD + 1), &mut encoder).unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        assert!(matches!(
+            decode(&bytes),
+            Err(BinaryLevelLoaderError::TooLarge)
+        ));
+    }
+}
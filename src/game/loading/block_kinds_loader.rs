@@ -0,0 +1,74 @@
+use super::super::block::{BlockFlags, TextureAtlasIndices};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use thiserror::Error;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ColliderShape {
+    #[default]
+    Cube,
+    /// The bottom half of the block
+    Slab,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct BlockKind {
+    pub texture_atlas_indices: TextureAtlasIndices,
+    #[serde(default)]
+    pub collider: ColliderShape,
+    /// Whether the player collides with it
+    #[serde(default = "solid")]
+    pub solid: bool,
+    #[serde(default)]
+    pub flags: BlockFlags,
+}
+
+fn solid() -> bool {
+    true
+}
+
+/// Every kind of block, by ID
+#[derive(Asset, TypePath, Deserialize, Serialize)]
+pub struct BlockKinds(pub BTreeMap<String, BlockKind>);
+
+/// The kind of blocks from levels before kinds existed
+pub const DEFAULT_KIND: &str = "solid";
+
+#[derive(Default)]
+struct BlockKindsLoader;
+
+#[non_exhaustive]
+#[derive(Error, Debug)]
+enum BlockKindsLoaderError {
+    #[error("Error reading block kinds: {0}")]
+    Io(#[from] io::Error),
+    #[error("Error parsing block kinds: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+impl AssetLoader for BlockKindsLoader {
+    type Asset = BlockKinds;
+    type Settings = ();
+    type Error = BlockKindsLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_asset_loader::<BlockKindsLoader>()
+        .init_asset::<BlockKinds>();
+}
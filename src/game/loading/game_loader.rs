@@ -1,19 +1,44 @@
+use super::super::abilities::Ability;
+use super::super::plane::Orientation;
+use super::world_loader::{START_ROOM, START_SPAWN_POINT};
 use bevy::asset::io::Reader;
 use bevy::asset::{AssetLoader, LoadContext};
 use bevy::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::io;
 use thiserror::Error;
 
-#[derive(Asset, TypePath, Deserialize, Resource)]
+/// Abilities every game has from the start
+const STARTING_ABILITIES: [Ability; 3] = [Ability::RotateX, Ability::RotateY, Ability::RotateZ];
+
+#[derive(Asset, TypePath, Deserialize, Serialize, Resource, Clone)]
+#[serde(default)]
 pub struct Game {
-    pub level: String,
+    pub world: String,
+    pub room: String,
+    pub spawn_point: String,
+    /// Where the player was when saving, instead of the spawn point
+    pub translation: Option<Vec3>,
+    pub orientation: Orientation,
+    /// The orientation of saves from before orientations were discrete
+    #[serde(skip_serializing)]
+    pub rotation: Option<Quat>,
+    pub abilities: HashSet<Ability>,
+    pub items: HashSet<String>,
 }
 
 impl Default for Game {
     fn default() -> Self {
         Self {
-            level: "level".into(),
+            world: "world".into(),
+            room: START_ROOM.into(),
+            spawn_point: START_SPAWN_POINT.into(),
+            translation: None,
+            orientation: Orientation::default(),
+            rotation: None,
+            abilities: HashSet::from(STARTING_ABILITIES),
+            items: HashSet::new(),
         }
     }
 }
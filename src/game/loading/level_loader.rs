@@ -1,20 +1,207 @@
+use super::super::abilities::Ability;
 use super::super::block::TextureAtlasIndices;
+use super::block_kinds_loader::DEFAULT_KIND;
 use bevy::asset::io::Reader;
 use bevy::asset::{AssetLoader, LoadContext};
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::io;
 use thiserror::Error;
 
 #[derive(Deserialize, Serialize)]
 pub struct Block {
     pub translation: Vec3,
-    pub texture_atlas_indices: TextureAtlasIndices,
+    /// The ID of the block's kind
+    pub kind: String,
+    /// Replaces the texture atlas indices of the kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture_atlas_indices: Option<TextureAtlasIndices>,
+}
+
+/// Something interactive placed in a level, told apart by its `type`
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LevelEntity {
+    /// Solid from its first to its last cell until a switch opens it, or the player touches it
+    /// holding its key
+    Door {
+        id: String,
+        min: IVec3,
+        max: IVec3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    /// Opens and closes the doors with the ID `door` when entered
+    Switch {
+        cell: IVec3,
+        door: String,
+    },
+    /// Kept in the game's items once collected
+    Key {
+        cell: IVec3,
+        id: String,
+    },
+    /// Goes through the cells of its waypoints in a loop, at `speed` blocks per second
+    MovingPlatform {
+        waypoints: Vec<IVec3>,
+        speed: f32,
+    },
+    /// Where the player enters the room from exits leading to `name`
+    SpawnPoint {
+        name: String,
+        cell: IVec3,
+    },
+    Pickup {
+        cell: IVec3,
+        ability: Ability,
+    },
+    Checkpoint {
+        cell: IVec3,
+    },
+}
+
+impl LevelEntity {
+    /// Whether the entity starts out in `cell`
+    pub fn contains(&self, cell: IVec3) -> bool {
+        match self {
+            Self::Door { min, max, .. } => cell.cmpge(*min).all() && cell.cmple(*max).all(),
+            Self::MovingPlatform { waypoints, .. } => waypoints.first() == Some(&cell),
+
+            Self::Switch { cell: own_cell, .. }
+            | Self::Key { cell: own_cell, .. }
+            | Self::SpawnPoint { cell: own_cell, .. }
+            | Self::Pickup { cell: own_cell, .. }
+            | Self::Checkpoint { cell: own_cell } => *own_cell == cell,
+        }
+    }
+}
+
+/// Kills the player once behind it, against its normal
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct KillPlane {
+    pub origin: Vec3,
+    pub normal: Vec3,
+}
+
+/// The cells the camera keeps its view inside
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Bounds {
+    /// First cell of the room
+    pub min: IVec3,
+    /// Last cell of the room, inclusive
+    pub max: IVec3,
 }
 
 #[derive(Asset, TypePath, Deserialize, Serialize)]
 pub struct Level {
+    pub version: u64,
+    /// Unbounded if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Bounds>,
     pub blocks: Vec<Block>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<LevelEntity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kill_planes: Vec<KillPlane>,
+}
+
+impl Bounds {
+    /// Grown to contain `cell`
+    pub fn including(self, cell: IVec3) -> Self {
+        Self {
+            min: self.min.min(cell),
+            max: self.max.max(cell),
+        }
+    }
+}
+
+impl Level {
+    /// The cells its blocks span, if it has any
+    pub fn extent(&self) -> Option<Bounds> {
+        let mut cells = self
+            .blocks
+            .iter()
+            .map(|block| block.translation.floor().as_ivec3());
+
+        let first = cells.next()?;
+
+        let bounds = Bounds {
+            min: first,
+            max: first,
+        };
+
+        Some(cells.fold(bounds, Bounds::including))
+    }
+}
+
+/// Upgrades a level document from the version at its index to the next one
+const MIGRATIONS: [fn(&mut Value); 3] = [add_version, add_kinds, add_entities];
+
+/// The version levels are loaded as and saved with
+pub const VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Levels before versioning only had `blocks`
+fn add_version(_level: &mut Value) {}
+
+/// Blocks only had texture atlas indices before kinds, which now override the kind's
+fn add_kinds(level: &mut Value) {
+    let Some(blocks) = level.get_mut("blocks").and_then(Value::as_array_mut) else {
+        return;
+    };
+
+    for block in blocks.iter_mut().filter_map(Value::as_object_mut) {
+        block.insert("kind".into(), Value::from(DEFAULT_KIND));
+    }
+}
+
+/// Pickups and checkpoints had lists of their own before entities
+fn add_entities(level: &mut Value) {
+    let Some(level) = level.as_object_mut() else {
+        return;
+    };
+
+    let mut entities = Vec::new();
+
+    if let Some(Value::Array(pickups)) = level.remove("pickups") {
+        for mut pickup in pickups {
+            if let Some(pickup) = pickup.as_object_mut() {
+                pickup.insert("type".into(), Value::from("pickup"));
+            }
+
+            entities.push(pickup);
+        }
+    }
+
+    if let Some(Value::Array(checkpoints)) = level.remove("checkpoints") {
+        for cell in checkpoints {
+            entities.push(json!({ "type": "checkpoint", "cell": cell }));
+        }
+    }
+
+    if !entities.is_empty() {
+        level.insert("entities".into(), Value::from(entities));
+    }
+}
+
+/// Runs every migration after the document's version, in order
+pub fn migrate(level: &mut Value) -> Result<(), LevelLoaderError> {
+    let version = level.get("version").cloned().unwrap_or(Value::from(0));
+
+    let migrations = version
+        .as_u64()
+        .and_then(|index| MIGRATIONS.get(index as usize..))
+        .ok_or(LevelLoaderError::Version(version))?;
+
+    for migration in migrations {
+        migration(level);
+    }
+
+    if let Some(level) = level.as_object_mut() {
+        level.insert("version".into(), Value::from(VERSION));
+    }
+
+    Ok(())
 }
 
 #[derive(Default)]
@@ -22,11 +209,13 @@ struct LevelLoader;
 
 #[non_exhaustive]
 #[derive(Error, Debug)]
-enum LevelLoaderError {
+pub enum LevelLoaderError {
     #[error("Error reading level: {0}")]
     Io(#[from] io::Error),
     #[error("Error parsing level: {0}")]
     Parse(#[from] serde_json::Error),
+    #[error("Unsupported level version: {0}")]
+    Version(Value),
 }
 
 impl AssetLoader for LevelLoader {
@@ -42,10 +231,96 @@ impl AssetLoader for LevelLoader {
     ) -> Result<Self::Asset, Self::Error> {
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes).await?;
-        Ok(serde_json::from_slice(&bytes)?)
+        let mut level = serde_json::from_slice(&bytes)?;
+        migrate(&mut level)?;
+        Ok(serde_json::from_value(level)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json"]
     }
 }
 
 pub fn plugin(app: &mut App) {
     app.init_asset_loader::<LevelLoader>().init_asset::<Level>();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(mut document: Value) -> Level {
+        migrate(&mut document).unwrap();
+        serde_json::from_value(document).unwrap()
+    }
+
+    #[test]
+    fn migrates_unversioned_level() {
+        let level = load(json!({
+            "blocks": [
+                {
+                    "translation": [1.0, 2.0, 3.0],
+                    "texture_atlas_indices": { "x": 4, "y": 5, "z": 6 }
+                }
+            ]
+        }));
+
+        assert_eq!(level.version, VERSION);
+        assert_eq!(level.blocks.len(), 1);
+        assert_eq!(level.blocks[0].translation, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(level.blocks[0].kind, DEFAULT_KIND);
+        assert!(
+            level.blocks[0].texture_atlas_indices == Some(TextureAtlasIndices { x: 4, y: 5, z: 6 })
+        );
+        assert!(level.entities.is_empty());
+    }
+
+    #[test]
+    fn migrates_pickups_and_checkpoints_to_entities() {
+        let level = load(json!({
+            "version": 2,
+            "blocks": [],
+            "pickups": [{ "cell": [0, 1, 0], "ability": "double_jump" }],
+            "checkpoints": [[2, 1, 0]],
+            "kill_planes": [{ "origin": [0.0, -8.0, 0.0], "normal": [0.0, 1.0, 0.0] }]
+        }));
+
+        assert_eq!(level.version, VERSION);
+        assert_eq!(level.entities.len(), 2);
+        assert!(matches!(
+            level.entities[0],
+            LevelEntity::Pickup {
+                cell: IVec3 { x: 0, y: 1, z: 0 },
+                ability: Ability::DoubleJump,
+            }
+        ));
+        assert!(matches!(
+            level.entities[1],
+            LevelEntity::Checkpoint {
+                cell: IVec3 { x: 2, y: 1, z: 0 },
+            }
+        ));
+        assert_eq!(level.kill_planes.len(), 1);
+    }
+
+    #[test]
+    fn keeps_current_level() {
+        let level = load(json!({
+            "version": VERSION,
+            "blocks": [],
+            "entities": [{ "type": "checkpoint", "cell": [0, 0, 0] }]
+        }));
+
+        assert_eq!(level.entities.len(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_level() {
+        let mut document = json!({ "version": VERSION + 1, "blocks": [] });
+
+        assert!(matches!(
+            migrate(&mut document),
+            Err(LevelLoaderError::Version(_))
+        ));
+    }
+}
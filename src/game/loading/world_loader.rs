@@ -0,0 +1,88 @@
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use thiserror::Error;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitKind {
+    /// Taken by walking into it
+    Edge,
+    /// Taken by pressing up while inside it
+    Door,
+    /// Taken by walking into it while the plane faces `normal`
+    Portal { normal: Vec3 },
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Exit {
+    pub kind: ExitKind,
+    /// First cell of the exit
+    pub min: IVec3,
+    /// Last cell of the exit, inclusive
+    pub max: IVec3,
+    pub room: String,
+    pub spawn_point: String,
+}
+
+impl Exit {
+    pub fn contains(&self, cell: IVec3) -> bool {
+        cell.cmpge(self.min).all() && cell.cmple(self.max).all()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Room {
+    pub level: String,
+    /// More can be placed in the level
+    #[serde(default)]
+    pub spawn_points: HashMap<String, Vec3>,
+    #[serde(default)]
+    pub exits: Vec<Exit>,
+}
+
+/// The room new games start in, and where unknown rooms and spawn points fall back to
+pub const START_ROOM: &str = "start";
+pub const START_SPAWN_POINT: &str = "start";
+
+#[derive(Asset, TypePath, Deserialize, Serialize)]
+pub struct WorldMap {
+    pub rooms: HashMap<String, Room>,
+}
+
+#[derive(Default)]
+struct WorldMapLoader;
+
+#[non_exhaustive]
+#[derive(Error, Debug)]
+enum WorldMapLoaderError {
+    #[error("Error reading world: {0}")]
+    Io(#[from] io::Error),
+    #[error("Error parsing world: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+impl AssetLoader for WorldMapLoader {
+    type Asset = WorldMap;
+    type Settings = ();
+    type Error = WorldMapLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_asset_loader::<WorldMapLoader>()
+        .init_asset::<WorldMap>();
+}
@@ -1,26 +1,146 @@
+use super::block;
+use super::block::Block;
+use super::block_map::BlockMap;
 use bevy::prelude::*;
-use std::f32::consts::FRAC_PI_2;
+use bevy::utils::HashSet;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::EPSILON;
+
+/// One of the 24 ways to rotate a cube onto itself, as where the local X, Y and Z axes point
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(try_from = "[IVec3; 3]", into = "[IVec3; 3]")]
+pub struct Orientation {
+    x: IVec3,
+    y: IVec3,
+    z: IVec3,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self {
+            x: IVec3::X,
+            y: IVec3::Y,
+            z: IVec3::Z,
+        }
+    }
+}
+
+impl Orientation {
+    /// Turned 90 degrees around its own `axis`, counterclockwise unless `clockwise`
+    pub fn turned(&self, axis: IVec3, clockwise: bool) -> Self {
+        let sign = if clockwise { -1 } else { 1 };
+        let turn = |v: IVec3| sign * axis.cross(v) + axis.dot(v) * axis;
+
+        Self {
+            x: self.apply(turn(IVec3::X)),
+            y: self.apply(turn(IVec3::Y)),
+            z: self.apply(turn(IVec3::Z)),
+        }
+    }
+
+    /// Where the plane faces, toward the camera
+    pub fn normal(&self) -> IVec3 {
+        self.z
+    }
+
+    fn apply(&self, v: IVec3) -> IVec3 {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+
+    pub fn quat(&self) -> Quat {
+        Quat::from_mat3(&Mat3::from_cols(
+            self.x.as_vec3(),
+            self.y.as_vec3(),
+            self.z.as_vec3(),
+        ))
+    }
+
+    /// The nearest orientation to `rotation`
+    pub fn from_quat(rotation: Quat) -> Self {
+        let x = (rotation * Vec3::X).round().as_ivec3();
+        let y = (rotation * Vec3::Y).round().as_ivec3();
+
+        Self {
+            x,
+            y,
+            z: x.cross(y),
+        }
+    }
+}
+
+impl TryFrom<[IVec3; 3]> for Orientation {
+    type Error = &'static str;
+
+    fn try_from([x, y, z]: [IVec3; 3]) -> Result<Self, Self::Error> {
+        let is_axis = |v: IVec3| v.abs().element_sum() == 1 && v.abs().max_element() == 1;
+
+        if !(is_axis(x) && is_axis(y) && x.cross(y) == z) {
+            return Err("Not a rotation of the X, Y and Z axes");
+        }
+
+        Ok(Self { x, y, z })
+    }
+}
+
+impl From<Orientation> for [IVec3; 3] {
+    fn from(orientation: Orientation) -> Self {
+        [orientation.x, orientation.y, orientation.z]
+    }
+}
 
 #[derive(Resource)]
 pub struct Rotation {
     previous: Option<Quat>,
-    next: Quat,
+    next: Orientation,
+    /// Orientations to undo back to, most recent last
+    history: Vec<Orientation>,
     pub transition_timer: Timer,
 }
 
+const HISTORY_LENGTH: usize = 64;
+
 impl Rotation {
-    pub fn set(&mut self, axis: &Vec3) {
-        self.previous = Some(self.next);
-        self.next *= Quat::from_axis_angle(*axis, FRAC_PI_2);
+    pub fn new(orientation: Orientation) -> Self {
+        Self {
+            next: orientation,
+            ..default()
+        }
+    }
+
+    /// Transitions to `orientation`, which undoes the last rotation if it goes back to it
+    pub fn set(&mut self, orientation: Orientation) {
+        if self.history.last() == Some(&orientation) {
+            self.history.pop();
+        } else {
+            if self.history.len() == HISTORY_LENGTH {
+                self.history.remove(0);
+            }
+
+            self.history.push(self.next);
+        }
+
+        self.previous = Some(self.get());
+        self.next = orientation;
         self.transition_timer.reset();
     }
 
+    /// The orientation before the last rotation
+    pub fn undone(&self) -> Option<Orientation> {
+        self.history.last().copied()
+    }
+
     pub fn get(&self) -> Quat {
         let Some(previous) = self.previous else {
-            return self.next;
+            return self.next.quat();
         };
 
-        previous.slerp(self.next, self.transition_timer.fraction())
+        previous.slerp(self.next.quat(), self.transition_timer.fraction())
+    }
+
+    /// The orientation once the transition finishes
+    pub fn target(&self) -> Orientation {
+        self.next
     }
 }
 
@@ -33,7 +153,8 @@ impl Default for Rotation {
 
         Self {
             previous: Option::default(),
-            next: Quat::default(),
+            next: Orientation::default(),
+            history: Vec::new(),
             transition_timer,
         }
     }
@@ -46,6 +167,195 @@ pub struct Translation;
 #[require(Transform)]
 pub struct Rotate;
 
+/// The slice of the level being played, derived from [`Rotation`] and [`Translation`]
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct Plane {
+    pub origin: Vec3,
+    pub normal: Vec3,
+}
+
+impl Default for Plane {
+    fn default() -> Self {
+        Self {
+            origin: Vec3::splat(0.5),
+            normal: Vec3::Z,
+        }
+    }
+}
+
+/// The blocks cut by the [`Plane`]
+#[derive(Resource, Default)]
+struct Sliced(HashSet<Entity>);
+
+/// Shown, and collided with if it has a collider, only while the [`Plane`] cuts it, like blocks
+///
+/// Holds the half size of the cuboid the plane has to cut.
+#[derive(Component)]
+#[require(Transform, Visibility)]
+pub struct Sliceable(pub Vec3);
+
+impl Default for Sliceable {
+    fn default() -> Self {
+        Self(Vec3::splat(block::SIZE / 2.0))
+    }
+}
+
 pub fn plugin(app: &mut App) {
-    app.init_resource::<Rotation>();
+    app.init_resource::<Rotation>()
+        .init_resource::<Plane>()
+        .init_resource::<Sliced>()
+        .add_systems(
+            Update,
+            (update, (slice, slice_entities))
+                .chain()
+                .run_if(in_state(super::State::Playing)),
+        );
+}
+
+pub fn update(
+    rotation: Res<Rotation>,
+    translation: Single<&GlobalTransform, With<Translation>>,
+    mut plane: ResMut<Plane>,
+) {
+    plane.set_if_neq(Plane {
+        origin: translation.translation().floor() + 0.5,
+        normal: rotation.get() * Vec3::Z,
+    });
+}
+
+/// Shows and enables collision only for the blocks cut by the [`Plane`], so what is seen is
+/// exactly what can be stood on
+///
+/// Blocks start hidden and without collision, so only the blocks on the plane are looked up
+/// when it moves, and only new blocks otherwise.
+fn slice(
+    mut commands: Commands,
+    plane: Res<Plane>,
+    block_map: Res<BlockMap>,
+    mut sliced: ResMut<Sliced>,
+    mut blocks: Query<(&Transform, &mut Visibility), With<Block>>,
+    added_blocks: Query<Entity, Added<Block>>,
+) {
+    let cut: HashSet<Entity> = if plane.is_changed() {
+        block_map
+            .on_plane(&plane)
+            .map(|(_cell, entity)| entity)
+            .collect()
+    } else {
+        added_blocks
+            .iter()
+            .filter(|&entity| {
+                blocks.get(entity).is_ok_and(|(transform, _visibility)| {
+                    block_intersects_plane(transform.translation, plane.origin, plane.normal)
+                })
+            })
+            .collect()
+    };
+
+    if plane.is_changed() {
+        for &entity in sliced.0.difference(&cut) {
+            let Ok((_transform, mut visibility)) = blocks.get_mut(entity) else {
+                continue;
+            };
+
+            visibility.set_if_neq(Visibility::Hidden);
+            // The editor or an unloading room may have despawned it this frame
+            commands.entity(entity).try_insert(ColliderDisabled);
+        }
+
+        sliced.0.clear();
+    }
+
+    for &entity in &cut {
+        let Ok((_transform, mut visibility)) = blocks.get_mut(entity) else {
+            continue;
+        };
+
+        visibility.set_if_neq(Visibility::Visible);
+        commands.entity(entity).remove::<ColliderDisabled>();
+    }
+
+    sliced.0.extend(cut);
+}
+
+/// Unlike blocks, [`Sliceable`] entities are few and can move, so all are checked every frame
+fn slice_entities(
+    mut commands: Commands,
+    plane: Res<Plane>,
+    mut entities: Query<(
+        Entity,
+        &Sliceable,
+        &Transform,
+        &mut Visibility,
+        Has<Collider>,
+        Has<ColliderDisabled>,
+    )>,
+) {
+    for (entity, sliceable, transform, mut visibility, collider, collider_disabled) in &mut entities
+    {
+        let cut = cuboid_intersects_plane(
+            transform.translation,
+            sliceable.0,
+            plane.origin,
+            plane.normal,
+        );
+
+        visibility.set_if_neq(if cut {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        });
+
+        if collider && cut == collider_disabled {
+            if cut {
+                commands.entity(entity).remove::<ColliderDisabled>();
+            } else {
+                commands.entity(entity).try_insert(ColliderDisabled);
+            }
+        }
+    }
+}
+
+const CORNERS: [Vec3; 8] = [
+    Vec3::new(1.0, 1.0, 1.0),
+    Vec3::new(1.0, 1.0, -1.0),
+    Vec3::new(1.0, -1.0, 1.0),
+    Vec3::new(-1.0, 1.0, 1.0),
+    Vec3::new(1.0, -1.0, -1.0),
+    Vec3::new(-1.0, 1.0, -1.0),
+    Vec3::new(-1.0, -1.0, 1.0),
+    Vec3::new(-1.0, -1.0, -1.0),
+];
+
+pub fn block_intersects_plane(block_center: Vec3, plane_origin: Vec3, plane_normal: Vec3) -> bool {
+    cuboid_intersects_plane(
+        block_center,
+        Vec3::splat(block::SIZE / 2.0),
+        plane_origin,
+        plane_normal,
+    )
+}
+
+/// Plane equation: Ax + By + Cz + D = 0
+pub fn cuboid_intersects_plane(
+    center: Vec3,
+    half_size: Vec3,
+    plane_origin: Vec3,
+    plane_normal: Vec3,
+) -> bool {
+    let plane_point = -plane_normal.dot(plane_origin);
+    let mut above = false;
+    let mut below = false;
+
+    for corner in CORNERS
+        .iter()
+        .map(move |&corner| center + half_size * corner)
+    {
+        let distance = plane_normal.dot(corner) + plane_point;
+
+        above |= distance > EPSILON;
+        below |= distance < -EPSILON;
+    }
+
+    above && below
 }
@@ -1,5 +1,9 @@
 use super::block;
-use super::plane::Translation;
+use super::health::Health;
+use super::input::Actions;
+use super::plane::{Rotation, Translation};
+use super::playing::state_machine;
+use bevy::math::Affine2;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
@@ -8,11 +12,11 @@ use bevy_rapier3d::prelude::*;
 	Name(name),
 	Translation,
     Transform,
-    // Mesh3d(mesh3d),
-    // MeshMaterial3d(mesh_material3d),
+    Visibility,
     KinematicCharacterController,
     Velocity,
     Collider(collider),
+    Health,
 )]
 pub struct Player;
 
@@ -20,10 +24,152 @@ fn name() -> Name {
     Name::new("Player")
 }
 
-// fn mesh3d() -> Mesh3d {}
-// fn mesh_material3d() -> MeshMaterial3d<StandardMaterial> {}
-
 fn collider() -> Collider {
     let half_extent = block::SIZE / 2.0;
     Collider::cuboid(half_extent, half_extent, half_extent)
 }
+
+const SHEET_FILE: &str = "player.png";
+/// Frames in the sprite sheet, all in one row
+const SHEET_COLUMNS: usize = 12;
+
+/// Consecutive frames of the sprite sheet
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Clip {
+    first: usize,
+    frames: usize,
+    frames_per_second: f32,
+}
+
+impl Clip {
+    fn of(state: &state_machine::State) -> Self {
+        use state_machine::State::*;
+
+        let (first, frames, frames_per_second) = match state {
+            Standing => (0, 2, 2.0),
+            Running => (2, 4, 10.0),
+            Jumping => (6, 1, 1.0),
+            Falling => (7, 1, 1.0),
+            WallSliding => (7, 1, 1.0),
+            WallJumping { .. } => (6, 1, 1.0),
+            Climbing => (2, 4, 6.0),
+            Swimming => (6, 2, 3.0),
+            Rotating(_orientation) => (8, 2, 4.0),
+            Shifting { .. } => (10, 2, 8.0),
+            Dashing { .. } => (2, 4, 20.0),
+        };
+
+        Self {
+            first,
+            frames,
+            frames_per_second,
+        }
+    }
+}
+
+/// The player's billboard, always facing the camera
+#[derive(Component)]
+#[require(Transform, Visibility)]
+struct PlayerSprite {
+    clip: Clip,
+    frame: usize,
+    timer: Timer,
+    /// Whether the player looks left, mirroring the sheet
+    flipped: bool,
+}
+
+pub fn plugin(app: &mut App) {
+    app.add_observer(spawn_sprite).add_systems(
+        Update,
+        (face, animate).run_if(in_state(super::State::Playing)),
+    );
+}
+
+fn spawn_sprite(
+    trigger: Trigger<OnAdd, Player>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let clip = Clip::of(&state_machine::State::default());
+
+    // Each player gets its own material, since its UVs pick the animation frame
+    let material = StandardMaterial {
+        base_color_texture: Some(asset_server.load(SHEET_FILE)),
+        unlit: true,
+        cull_mode: None,
+        alpha_mode: AlphaMode::Mask(0.5),
+        uv_transform: uv_transform(clip.first),
+        ..default()
+    };
+
+    commands.entity(trigger.entity()).with_child((
+        PlayerSprite {
+            clip,
+            frame: 0,
+            timer: Timer::from_seconds(1.0 / clip.frames_per_second, TimerMode::Repeating),
+            flipped: false,
+        },
+        Mesh3d(meshes.add(Rectangle::from_length(block::SIZE))),
+        MeshMaterial3d(materials.add(material)),
+    ));
+}
+
+/// Selects frame `index` of the sheet
+fn uv_transform(index: usize) -> Affine2 {
+    let width = 1.0 / SHEET_COLUMNS as f32;
+    Affine2::from_scale_angle_translation(
+        Vec2::new(width, 1.0),
+        0.0,
+        Vec2::new(index as f32 * width, 0.0),
+    )
+}
+
+/// Turns the sprite toward the camera and the way the player moves
+fn face(
+    actions: Res<Actions>,
+    rotation: Res<Rotation>,
+    mut sprites: Query<(&mut PlayerSprite, &mut Transform)>,
+) {
+    let direction = actions.movement().x;
+
+    for (mut sprite, mut transform) in &mut sprites {
+        if direction != 0.0 {
+            sprite.flipped = direction < 0.0;
+        }
+
+        transform.rotation = rotation.get();
+        transform.scale.x = if sprite.flipped { -1.0 } else { 1.0 };
+    }
+}
+
+fn animate(
+    time: Res<Time>,
+    state: Res<state_machine::State>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut sprites: Query<(&mut PlayerSprite, &MeshMaterial3d<StandardMaterial>)>,
+) {
+    let clip = Clip::of(&state);
+
+    for (mut sprite, material) in &mut sprites {
+        let previous_index = sprite.clip.first + sprite.frame;
+
+        if sprite.clip != clip {
+            sprite.clip = clip;
+            sprite.frame = 0;
+            sprite.timer = Timer::from_seconds(1.0 / clip.frames_per_second, TimerMode::Repeating);
+        } else if sprite.timer.tick(time.delta()).just_finished() {
+            sprite.frame =
+                (sprite.frame + sprite.timer.times_finished_this_tick() as usize) % clip.frames;
+        }
+
+        let index = sprite.clip.first + sprite.frame;
+
+        if index != previous_index {
+            if let Some(material) = materials.get_mut(&material.0) {
+                material.uv_transform = uv_transform(index);
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use super::super::block_map;
+use super::super::input::{Action, Actions};
+use super::super::loading::world_loader::{ExitKind, WorldMap};
+use super::super::loading::{LoadingWorld, Location};
+use super::super::player::Player;
+use super::plane::Plane;
+use bevy::prelude::*;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(Update, exit.run_if(in_state(super::super::State::Playing)));
+}
+
+const PORTAL_TOLERANCE: f32 = 0.01;
+
+/// Leaves the room through the exit the player is in, reloading the level of the room behind it
+fn exit(
+    actions: Res<Actions>,
+    worlds: Res<Assets<WorldMap>>,
+    loading_world: Res<LoadingWorld>,
+    plane: Res<Plane>,
+    mut location: ResMut<Location>,
+    player: Single<&GlobalTransform, With<Player>>,
+    mut next_state: ResMut<NextState<super::super::State>>,
+) {
+    // Unknown rooms are sent back to the start while loading
+    let Some(room) = worlds
+        .get(loading_world.0.id())
+        .and_then(|world_map| world_map.rooms.get(&location.room))
+    else {
+        return;
+    };
+
+    let cell = block_map::cell(player.translation());
+
+    let Some(exit) = room.exits.iter().find(|exit| {
+        exit.contains(cell)
+            && match &exit.kind {
+                ExitKind::Edge => true,
+                ExitKind::Door => actions.just_pressed(Action::Enter),
+                ExitKind::Portal { normal } => plane.normal.abs_diff_eq(*normal, PORTAL_TOLERANCE),
+            }
+    }) else {
+        return;
+    };
+
+    *location = Location {
+        room: exit.room.clone(),
+        spawn_point: exit.spawn_point.clone(),
+        translation: None,
+    };
+
+    next_state.set(super::super::State::Loading);
+}
@@ -2,5 +2,5 @@ use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
 pub fn plugin(app: &mut App) {
-    app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default());
+    app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default().in_fixed_schedule());
 }
@@ -0,0 +1,210 @@
+//! Keeps plane rotations and depth shifts from trapping the player inside a block or over a
+//! bottomless drop.
+
+use super::super::block;
+use super::super::block::Block;
+use super::super::block_map;
+use super::super::block_map::BlockMap;
+use super::plane::{block_intersects_plane, Rotate, Rotation};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::f32::consts::PI;
+
+/// How many cells below the player are searched for ground
+const GROUND_DEPTH: i32 = 64;
+/// Keeps a player touching a block from counting as inside it
+const SKIN: f32 = 0.01;
+
+pub enum Legality {
+    Legal,
+    /// Legal once the player moves to this translation
+    Nudge(Vec3),
+    Illegal,
+}
+
+/// Whether the player at `translation` can end up at `rotation`, or the nearest cell where they can
+/// that they can also reach from there
+pub fn check(
+    rotation: Quat,
+    translation: Vec3,
+    block_map: &BlockMap,
+    blocks: &Query<&CollisionGroups, With<Block>>,
+) -> Legality {
+    if fits(rotation, translation, block_map, blocks) {
+        return Legality::Legal;
+    }
+
+    let cell = block_map::cell(translation);
+
+    let mut candidates: Vec<(IVec3, Vec3)> = (-1..=1)
+        .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+        .map(|offset| (offset, (cell + offset).as_vec3() + 0.5))
+        .collect();
+
+    // Face neighbours before edge and corner ones, then the nearest
+    candidates.sort_by(|(a_offset, a), (b_offset, b)| {
+        let a_steps = a_offset.abs().element_sum();
+        let b_steps = b_offset.abs().element_sum();
+
+        a_steps.cmp(&b_steps).then(
+            a.distance_squared(translation)
+                .total_cmp(&b.distance_squared(translation)),
+        )
+    });
+
+    candidates
+        .into_iter()
+        .map(|(_offset, candidate)| candidate)
+        .find(|&candidate| {
+            reachable(translation, candidate, block_map, blocks)
+                && fits(rotation, candidate, block_map, blocks)
+        })
+        .map_or(Legality::Illegal, Legality::Nudge)
+}
+
+/// How far apart the positions along a nudge are checked for blocks in the way
+const NUDGE_STEP: f32 = 0.25;
+
+/// Whether the player can move straight from `from` to `to` without going through a solid block,
+/// in any slice
+fn reachable(
+    from: Vec3,
+    to: Vec3,
+    block_map: &BlockMap,
+    blocks: &Query<&CollisionGroups, With<Block>>,
+) -> bool {
+    let steps = (from.distance(to) / NUDGE_STEP).ceil().max(1.0) as u32;
+
+    (0..=steps).all(|step| {
+        let translation = from.lerp(to, step as f32 / steps as f32);
+
+        overlapped(translation).all(|cell| !is(cell, block::SOLID, block_map, blocks))
+    })
+}
+
+/// The cells the player at `translation` overlaps
+fn overlapped(translation: Vec3) -> impl Iterator<Item = IVec3> {
+    let half_extents = Vec3::splat(block::SIZE / 2.0 - SKIN);
+    let min = block_map::cell(translation - half_extents);
+    let max = block_map::cell(translation + half_extents);
+
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
+/// Whether the player has ground below and fits in the slice at `rotation`
+pub fn fits(
+    rotation: Quat,
+    translation: Vec3,
+    block_map: &BlockMap,
+    blocks: &Query<&CollisionGroups, With<Block>>,
+) -> bool {
+    if !free(rotation, translation, block_map, blocks) {
+        return false;
+    }
+
+    // The column below the player always lies in the slice, as the plane contains the up axis
+    let down = (rotation * Vec3::NEG_Y).round().as_ivec3();
+    let cell = block_map::cell(translation);
+
+    (1..=GROUND_DEPTH).any(|depth| {
+        is(
+            cell + down * depth,
+            block::SOLID | block::ONE_WAY,
+            block_map,
+            blocks,
+        )
+    })
+}
+
+/// Whether the player overlaps no solid block in the slice at `rotation`
+fn free(
+    rotation: Quat,
+    translation: Vec3,
+    block_map: &BlockMap,
+    blocks: &Query<&CollisionGroups, With<Block>>,
+) -> bool {
+    let plane_origin = translation.floor() + 0.5;
+    let plane_normal = rotation * Vec3::Z;
+
+    overlapped(translation).all(|cell| {
+        !(is(cell, block::SOLID, block_map, blocks)
+            && block_intersects_plane(cell.as_vec3() + 0.5, plane_origin, plane_normal))
+    })
+}
+
+/// Whether a block in one of `groups` occupies `cell`
+fn is(
+    cell: IVec3,
+    groups: Group,
+    block_map: &BlockMap,
+    blocks: &Query<&CollisionGroups, With<Block>>,
+) -> bool {
+    block_map
+        .get(cell)
+        .and_then(|entity| blocks.get(entity).ok())
+        .is_some_and(|collision_groups| collision_groups.memberships.intersects(groups))
+}
+
+/// Triggered when a rotation around the local axis is refused
+#[derive(Event)]
+pub struct Refused(pub Vec3);
+
+const SHAKE_DURATION: f32 = 0.3;
+const SHAKE_ANGLE: f32 = 0.05;
+const SHAKES: f32 = 3.0;
+
+/// Wobbles the camera toward a refused rotation
+#[derive(Resource)]
+struct Shake {
+    axis: Vec3,
+    timer: Timer,
+}
+
+impl Default for Shake {
+    fn default() -> Self {
+        let mut timer = Timer::from_seconds(SHAKE_DURATION, TimerMode::Once);
+        timer.tick(timer.remaining());
+
+        Self {
+            axis: Vec3::X,
+            timer,
+        }
+    }
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<Shake>()
+        .add_observer(refuse)
+        .add_systems(Update, shake.run_if(in_state(super::super::State::Playing)));
+}
+
+fn refuse(trigger: Trigger<Refused>, mut shake: ResMut<Shake>) {
+    // Holding the rotation keeps refusing, so let each shake finish
+    if shake.timer.finished() {
+        shake.axis = trigger.event().0;
+        shake.timer.reset();
+    }
+}
+
+fn shake(
+    time: Res<Time>,
+    rotation: Res<Rotation>,
+    mut shake: ResMut<Shake>,
+    mut transforms: Query<&mut Transform, With<Rotate>>,
+) {
+    if shake.timer.finished() {
+        return;
+    }
+
+    shake.timer.tick(time.delta());
+
+    let angle = SHAKE_ANGLE
+        * (shake.timer.fraction() * SHAKES * PI).sin()
+        * shake.timer.fraction_remaining();
+
+    for mut transform in &mut transforms {
+        transform.rotation = rotation.get() * Quat::from_axis_angle(shake.axis, angle);
+    }
+}
@@ -1,10 +1,19 @@
+use super::super::abilities::Ability;
 use super::super::block;
-use super::super::block::Block;
+use super::super::block::{Block, BlockFlags};
+use super::super::block_map;
+use super::super::block_map::BlockMap;
+use super::super::checkpoints::Respawning;
+use super::super::input::{Action, Actions};
 use super::super::player::Player;
-use super::plane::{Rotate, Rotation, Translation};
+use super::super::save::Progress;
+use super::plane::{Orientation, Rotate, Rotation};
+use super::rotation;
+use super::rotation::{Legality, Refused};
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
-use std::f32::EPSILON;
+use std::collections::HashSet;
+use std::mem;
 use std::mem::discriminant;
 
 #[derive(Resource, Default, Debug, Clone)]
@@ -12,14 +21,54 @@ pub enum State {
     #[default]
     Standing,
     Running,
-    Jumping(Timer),
+    Jumping,
     Falling,
-    Rotating(Vec3),
+    /// Sliding down a wall the player pushes against
+    WallSliding,
+    /// Kicked off a wall, `away` being -1 or 1 along the plane's X axis
+    WallJumping {
+        away: f32,
+        timer: Timer,
+    },
+    /// Moving along a ladder, without gravity
+    Climbing,
+    /// Sinking slowly through water, pushed up by each jump press
+    Swimming,
+    Rotating(Orientation),
+    /// Bursting sideways, `direction` being -1 or 1 along the plane's X axis
+    Dashing {
+        direction: f32,
+        timer: Timer,
+    },
+    /// Moving to the parallel slice at `to`
+    Shifting {
+        from: Vec3,
+        to: Vec3,
+        timer: Timer,
+    },
 }
 
 impl State {
-    fn jumping() -> Self {
-        Self::Jumping(Timer::from_seconds(0.5, TimerMode::Once))
+    fn shifting(from: Vec3, to: Vec3) -> Self {
+        Self::Shifting {
+            from,
+            to,
+            timer: Timer::from_seconds(SHIFTING_DURATION, TimerMode::Once),
+        }
+    }
+
+    fn dashing(direction: f32, duration: f32) -> Self {
+        Self::Dashing {
+            direction,
+            timer: Timer::from_seconds(duration, TimerMode::Once),
+        }
+    }
+
+    fn wall_jumping(away: f32, duration: f32) -> Self {
+        Self::WallJumping {
+            away,
+            timer: Timer::from_seconds(duration, TimerMode::Once),
+        }
     }
 }
 
@@ -29,74 +78,307 @@ impl PartialEq for State {
     }
 }
 
+/// How the player moves, in blocks and seconds
+#[derive(Resource, Debug, Clone)]
+pub struct PlayerTuning {
+    pub gravity: f32,
+    pub terminal_velocity: f32,
+    pub running_speed: f32,
+    pub running_acceleration: f32,
+    pub jumping_velocity: f32,
+    pub air_speed: f32,
+    pub air_acceleration: f32,
+    /// How long after walking off a ledge a jump still works
+    pub coyote_time: f32,
+    /// How long before landing a jump press still counts
+    pub jump_buffer: f32,
+    /// What is left of the upward velocity when jump is released early
+    pub jump_cut: f32,
+    pub dash_speed: f32,
+    pub dash_duration: f32,
+    /// Fastest fall while sliding down a wall
+    pub wall_sliding_speed: f32,
+    /// How fast a wall jump pushes away from the wall
+    pub wall_jump_velocity: f32,
+    /// How long after a wall jump the player can't steer back to the wall
+    pub wall_jump_duration: f32,
+    pub climbing_speed: f32,
+    pub swimming_speed: f32,
+    pub swimming_acceleration: f32,
+    /// Gravity in water
+    pub buoyant_gravity: f32,
+    /// Fastest fall in water
+    pub sinking_speed: f32,
+    /// How fast each jump press pushes up in water
+    pub stroke_velocity: f32,
+}
+
+impl Default for PlayerTuning {
+    fn default() -> Self {
+        Self {
+            gravity: 30.0,
+            terminal_velocity: 20.0,
+            running_speed: 8.0,
+            running_acceleration: 60.0,
+            jumping_velocity: 12.0,
+            air_speed: 5.0,
+            air_acceleration: 30.0,
+            coyote_time: 0.1,
+            jump_buffer: 0.1,
+            jump_cut: 0.5,
+            dash_speed: 16.0,
+            dash_duration: 0.15,
+            wall_sliding_speed: 3.0,
+            wall_jump_velocity: 8.0,
+            wall_jump_duration: 0.15,
+            climbing_speed: 4.0,
+            swimming_speed: 4.0,
+            swimming_acceleration: 20.0,
+            buoyant_gravity: 8.0,
+            sinking_speed: 2.0,
+            stroke_velocity: 5.0,
+        }
+    }
+}
+
+/// When jump was pressed and the ground last touched, for the grace windows of [`PlayerTuning`]
+#[derive(Default)]
+struct JumpTiming {
+    /// Seconds since jump was pressed, while it may still start a jump
+    since_pressed: Option<f32>,
+    /// Seconds since the player was grounded, while a jump may still start from there
+    since_grounded: Option<f32>,
+}
+
+/// The actions held in the last step, to tell new presses apart
+#[derive(Default)]
+struct Held(HashSet<Action>);
+
+/// Abilities that can only be used once until the player is grounded again
+#[derive(Default)]
+struct Spent {
+    air_jump: bool,
+    dash: bool,
+}
+
 pub fn plugin(app: &mut App) {
-    app.init_resource::<State>().add_systems(
-        Update,
-        state_machine.run_if(in_state(super::super::State::Playing)),
-    );
+    app.init_resource::<State>()
+        .init_resource::<PlayerTuning>()
+        .add_systems(
+            FixedUpdate,
+            state_machine
+                .run_if(in_state(super::super::State::Playing))
+                .run_if(not(any_with_component::<Respawning>)),
+        );
 }
 
-/// Gravitational constant
-const G: f32 = 10.0;
-const RUNNING_SPEED: f32 = 8.0;
-const JUMPING_FORCE: Vec3 = Vec3::new(0.0, 30.0, 0.0);
-const JUMPING_SPEED: f32 = 5.0;
-const FALLING_SPEED: f32 = 5.0;
+const SHIFTING_DURATION: f32 = 0.25;
+
+/// How much of the movement along an axis can be blocked before it counts as a contact
+const CONTACT_THRESHOLD: f32 = 0.5;
 
-fn state_machine(
-    keyboard: Res<ButtonInput<KeyCode>>,
+pub fn state_machine(
+    mut commands: Commands,
+    actions: Res<Actions>,
     time: Res<Time>,
+    tuning: Res<PlayerTuning>,
+    progress: Res<Progress>,
     mut state: ResMut<State>,
     mut plane_rotation: ResMut<Rotation>,
     mut previous_state: Local<State>,
-    output: Option<Single<&KinematicCharacterControllerOutput>>,
-    mut controller: Single<&mut KinematicCharacterController>,
+    mut jump_timing: Local<JumpTiming>,
+    mut held: Local<Held>,
+    mut spent: Local<Spent>,
     mut transforms: Query<&mut Transform, (With<Rotate>, Without<Player>)>,
-    plane_translation: Single<&GlobalTransform, With<Translation>>,
-    mut blocks: Query<(&GlobalTransform, &mut Visibility), With<Block>>,
-    mut player: Single<(&mut Velocity, &mut Transform), With<Player>>,
+    player: Single<
+        (
+            &mut Velocity,
+            &mut Transform,
+            &mut KinematicCharacterController,
+            Option<&KinematicCharacterControllerOutput>,
+        ),
+        With<Player>,
+    >,
+    block_map: Res<BlockMap>,
+    blocks: Query<&CollisionGroups, With<Block>>,
+    block_flags: Query<&BlockFlags>,
 ) {
-    let mut direction = Vec2::ZERO;
+    let (mut velocity, mut player_transform, mut controller, output) = player.into_inner();
+    let direction = actions.movement().x;
+    let vertical_direction = actions.movement().y;
+
+    // What the block the player is in does to them
+    let flags = block_map
+        .get(block_map::cell(player_transform.translation))
+        .and_then(|entity| block_flags.get(entity).ok())
+        .copied()
+        .unwrap_or_default();
+
+    let climb = flags.ladder && vertical_direction != 0.0;
+
+    let up = controller.up;
+    let right = plane_rotation.get() * Vec3::X;
+    let mut vertical_velocity = velocity.linvel.dot(up);
+    let mut lateral_velocity = velocity.linvel.dot(right);
 
-    if keyboard.pressed(KeyCode::KeyW) {
-        direction.y += 1.0;
+    // Contacts from the last physics step
+    let grounded = output.as_ref().map_or(false, |output| output.grounded);
+
+    let blocked = |axis: Vec3| {
+        output.as_ref().map_or(false, |output| {
+            let desired = output.desired_translation.dot(axis);
+            let effective = output.effective_translation.dot(axis);
+            desired > 0.0 && effective < desired * CONTACT_THRESHOLD
+        })
+    };
+
+    let delta_time = time.delta_secs();
+
+    // Steps are fixed, so presses are found from what was held in the last step rather than per
+    // frame, and holding an action only uses it once
+    let previously_held = mem::replace(&mut held.0, actions.held().clone());
+    let just_pressed = |action| actions.pressed(action) && !previously_held.contains(&action);
+
+    let jump_held = actions.pressed(Action::Jump);
+
+    jump_timing.since_pressed = if just_pressed(Action::Jump) {
+        Some(0.0)
+    } else {
+        jump_timing
+            .since_pressed
+            .map(|seconds| seconds + delta_time)
+            .filter(|&seconds| seconds <= tuning.jump_buffer)
+    };
+
+    jump_timing.since_grounded = if grounded {
+        Some(0.0)
+    } else {
+        jump_timing
+            .since_grounded
+            .map(|seconds| seconds + delta_time)
+            .filter(|&seconds| seconds <= tuning.coyote_time)
+    };
+
+    let jump = jump_timing.since_pressed.is_some() && jump_timing.since_grounded.is_some();
+
+    let unlocked = |ability| progress.abilities.contains(&ability);
+
+    if grounded {
+        *spent = Spent::default();
     }
 
-    if keyboard.pressed(KeyCode::KeyA) {
-        direction.x -= 1.0;
+    let dash = just_pressed(Action::Dash) && unlocked(Ability::Dash) && !spent.dash;
+
+    // Without input, the way the player is already going
+    let facing = if direction != 0.0 {
+        direction.signum()
+    } else {
+        lateral_velocity.signum()
+    };
+
+    if grounded && vertical_velocity < 0.0 {
+        vertical_velocity = 0.0;
     }
 
-    if keyboard.pressed(KeyCode::KeyS) {
-        direction.y -= 1.0;
+    if blocked(up) && vertical_velocity > 0.0 {
+        vertical_velocity = 0.0;
     }
 
-    if keyboard.pressed(KeyCode::KeyD) {
-        direction.x += 1.0;
+    if (blocked(right) && lateral_velocity > 0.0) || (blocked(-right) && lateral_velocity < 0.0) {
+        lateral_velocity = 0.0;
     }
 
-    let direction = direction.normalize_or_zero().extend(0.0);
-    let grounded = output.as_ref().map_or(false, |output| output.grounded);
+    // The side of the wall the player pushes against, if any, along the plane's X axis so it
+    // stays sideways to the plane's up whichever way the plane is rotated
+    let wall = if blocked(right) {
+        1.0
+    } else if blocked(-right) {
+        -1.0
+    } else {
+        0.0
+    };
+
+    // The parallel slice to shift to, once per press, if the player fits there with ground below
+    let depth_shift = match (
+        just_pressed(Action::DepthShiftIn),
+        just_pressed(Action::DepthShiftOut),
+    ) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => 0.0,
+    };
+
+    let orientation = plane_rotation.target();
+
+    let depth_shift_target = (depth_shift != 0.0)
+        .then(|| player_transform.translation - orientation.normal().as_vec3() * depth_shift)
+        .filter(|&to| rotation::fits(orientation.quat(), to, &block_map, &blocks));
 
     // Input
     let next_state: Option<State> = match &*state {
         State::Standing => 'standing: {
-            if keyboard.pressed(KeyCode::Digit1) {
-                break 'standing Some(State::Rotating(Vec3::X));
+            // Counter-rotations first, as their default chords contain the rotation ones
+            let target = [
+                (Action::CounterRotateX, Ability::RotateX, IVec3::X, true),
+                (Action::CounterRotateY, Ability::RotateY, IVec3::Y, true),
+                (Action::CounterRotateZ, Ability::RotateZ, IVec3::Z, true),
+                (Action::RotateX, Ability::RotateX, IVec3::X, false),
+                (Action::RotateY, Ability::RotateY, IVec3::Y, false),
+                (Action::RotateZ, Ability::RotateZ, IVec3::Z, false),
+            ]
+            .into_iter()
+            .map(|(action, ability, axis, clockwise)| {
+                let target = unlocked(ability).then(|| orientation.turned(axis, clockwise));
+                (action, target)
+            })
+            .chain([(Action::UndoRotation, plane_rotation.undone())])
+            .find_map(|(action, target)| just_pressed(action).then_some(target).flatten());
+
+            if let Some(target) = target {
+                let translation = player_transform.translation;
+
+                match rotation::check(target.quat(), translation, &block_map, &blocks) {
+                    Legality::Legal => {}
+
+                    Legality::Nudge(translation) => {
+                        player_transform.translation = translation;
+                    }
+
+                    Legality::Illegal => {
+                        let turn = orientation.quat().inverse() * target.quat();
+                        commands.trigger(Refused(turn.to_axis_angle().0));
+                        break 'standing None;
+                    }
+                }
+
+                break 'standing Some(State::Rotating(target));
+            }
+
+            if flags.water {
+                break 'standing Some(State::Swimming);
+            }
+
+            if climb {
+                break 'standing Some(State::Climbing);
+            }
+
+            if let Some(to) = depth_shift_target {
+                break 'standing Some(State::shifting(player_transform.translation, to));
             }
 
-            if keyboard.pressed(KeyCode::Digit2) {
-                break 'standing Some(State::Rotating(Vec3::Y));
+            if dash {
+                break 'standing Some(State::dashing(facing, tuning.dash_duration));
             }
 
-            if keyboard.pressed(KeyCode::Digit3) {
-                break 'standing Some(State::Rotating(Vec3::Z));
+            if jump {
+                break 'standing Some(State::Jumping);
             }
 
-            if keyboard.pressed(KeyCode::Space) {
-                break 'standing Some(State::jumping());
+            if !grounded {
+                break 'standing Some(State::Falling);
             }
 
-            if direction.length() > 0.0 {
+            if direction != 0.0 {
                 break 'standing Some(State::Running);
             }
 
@@ -104,19 +386,57 @@ fn state_machine(
         }
 
         State::Running => 'running: {
-            if keyboard.pressed(KeyCode::Space) {
-                break 'running Some(State::jumping());
+            if flags.water {
+                break 'running Some(State::Swimming);
             }
 
-            if direction.length() <= 0.0 {
+            if climb {
+                break 'running Some(State::Climbing);
+            }
+
+            if let Some(to) = depth_shift_target {
+                break 'running Some(State::shifting(player_transform.translation, to));
+            }
+
+            if dash {
+                break 'running Some(State::dashing(facing, tuning.dash_duration));
+            }
+
+            if jump {
+                break 'running Some(State::Jumping);
+            }
+
+            if !grounded {
+                break 'running Some(State::Falling);
+            }
+
+            if direction == 0.0 {
                 break 'running Some(State::Standing);
             }
 
             None
         }
 
-        State::Jumping(timer) => 'jumping: {
-            if timer.just_finished() {
+        State::Jumping => 'jumping: {
+            if flags.water {
+                break 'jumping Some(State::Swimming);
+            }
+
+            if climb {
+                break 'jumping Some(State::Climbing);
+            }
+
+            if dash {
+                break 'jumping Some(State::dashing(facing, tuning.dash_duration));
+            }
+
+            if vertical_velocity <= 0.0 {
+                break 'jumping Some(State::Falling);
+            }
+
+            // Letting go early makes for a lower jump
+            if !jump_held {
+                vertical_velocity *= tuning.jump_cut;
                 break 'jumping Some(State::Falling);
             }
 
@@ -124,20 +444,134 @@ fn state_machine(
         }
 
         State::Falling => 'falling: {
+            if flags.water {
+                break 'falling Some(State::Swimming);
+            }
+
+            if climb {
+                break 'falling Some(State::Climbing);
+            }
+
+            // Just off a ledge, or about to land with jump pressed
+            if jump {
+                break 'falling Some(State::Jumping);
+            }
+
+            if dash {
+                break 'falling Some(State::dashing(facing, tuning.dash_duration));
+            }
+
             if grounded {
                 break 'falling Some(State::Standing);
             }
 
+            if wall != 0.0 && unlocked(Ability::WallJump) {
+                break 'falling Some(State::WallSliding);
+            }
+
+            if jump_timing.since_pressed.is_some()
+                && !spent.air_jump
+                && unlocked(Ability::DoubleJump)
+            {
+                spent.air_jump = true;
+                break 'falling Some(State::Jumping);
+            }
+
             None
         }
 
-        State::Rotating(_axis) => 'rotating: {
+        State::WallSliding => 'wall_sliding: {
+            if flags.water {
+                break 'wall_sliding Some(State::Swimming);
+            }
+
+            if climb {
+                break 'wall_sliding Some(State::Climbing);
+            }
+
+            if grounded {
+                break 'wall_sliding Some(State::Standing);
+            }
+
+            if wall == 0.0 {
+                break 'wall_sliding Some(State::Falling);
+            }
+
+            if jump_timing.since_pressed.is_some() {
+                break 'wall_sliding Some(State::wall_jumping(-wall, tuning.wall_jump_duration));
+            }
+
+            None
+        }
+
+        State::WallJumping { timer, .. } => 'wall_jumping: {
+            if timer.just_finished() {
+                break 'wall_jumping Some(State::Jumping);
+            }
+
+            if vertical_velocity <= 0.0 {
+                break 'wall_jumping Some(State::Falling);
+            }
+
+            None
+        }
+
+        State::Climbing => 'climbing: {
+            if jump_timing.since_pressed.is_some() {
+                break 'climbing Some(State::Jumping);
+            }
+
+            if !flags.ladder {
+                break 'climbing Some(State::Falling);
+            }
+
+            if grounded && vertical_direction < 0.0 {
+                break 'climbing Some(State::Standing);
+            }
+
+            None
+        }
+
+        State::Swimming => 'swimming: {
+            // Jumping out of the water while still swimming up
+            if !flags.water {
+                break 'swimming Some(if jump_held {
+                    State::Jumping
+                } else {
+                    State::Falling
+                });
+            }
+
+            if climb {
+                break 'swimming Some(State::Climbing);
+            }
+
+            None
+        }
+
+        State::Rotating(_orientation) => 'rotating: {
             if plane_rotation.transition_timer.just_finished() {
                 break 'rotating Some(previous_state.clone());
             }
 
             None
         }
+
+        State::Dashing { timer, .. } => 'dashing: {
+            if timer.just_finished() {
+                break 'dashing Some(State::Falling);
+            }
+
+            None
+        }
+
+        State::Shifting { timer, .. } => 'shifting: {
+            if timer.just_finished() {
+                break 'shifting Some(previous_state.clone());
+            }
+
+            None
+        }
     };
 
     let mut enter = false;
@@ -156,38 +590,113 @@ fn state_machine(
         match &*state {
             State::Standing => {}
             State::Running => {}
-            State::Jumping(_timer) => {}
+
+            // Wall jumps have already kicked off, and only hand over the rest of the rise
+            State::Jumping if matches!(*previous_state, State::WallJumping { .. }) => {}
+
+            State::Jumping => {
+                vertical_velocity = tuning.jumping_velocity;
+
+                // Each press and each time on the ground is good for one jump
+                jump_timing.since_pressed = None;
+                jump_timing.since_grounded = None;
+            }
+
             State::Falling => {}
 
-            State::Rotating(axis) => {
-                plane_rotation.set(axis);
+            // A wall is as good as ground to jump again from
+            State::WallSliding => {
+                *spent = Spent::default();
             }
-        }
-    }
 
-    // Update
-    let mass = 1.0;
-    let gravity = mass * G * Vec3::NEG_Y;
-    let mut forces = vec![gravity];
+            State::WallJumping { away, .. } => {
+                vertical_velocity = tuning.jumping_velocity;
+                lateral_velocity = away * tuning.wall_jump_velocity;
+                jump_timing.since_pressed = None;
+                jump_timing.since_grounded = None;
+            }
 
-    match &mut *state {
-        State::Standing => {}
+            // Ladders and water are as good as ground to jump again from
+            State::Climbing => {
+                *spent = Spent::default();
+            }
 
-        State::Running => {
-            forces.push(direction * RUNNING_SPEED);
+            // Diving in slows down to sinking
+            State::Swimming => {
+                *spent = Spent::default();
+                vertical_velocity = vertical_velocity.max(-tuning.sinking_speed);
+            }
+
+            State::Rotating(orientation) => {
+                plane_rotation.set(*orientation);
+            }
+
+            State::Dashing { .. } => {
+                spent.dash = true;
+            }
+
+            State::Shifting { .. } => {}
         }
+    }
 
-        State::Jumping(timer) => {
+    // Update
+    let (target_velocity, acceleration, gravity, terminal_velocity) = match &mut *state {
+        State::Standing | State::Running => (
+            direction * tuning.running_speed,
+            tuning.running_acceleration,
+            tuning.gravity,
+            tuning.terminal_velocity,
+        ),
+
+        State::Jumping | State::Falling => (
+            direction * tuning.air_speed,
+            tuning.air_acceleration,
+            tuning.gravity,
+            tuning.terminal_velocity,
+        ),
+
+        // Still pushing into the wall, or it would no longer be blocking
+        State::WallSliding => (
+            direction * tuning.air_speed,
+            tuning.air_acceleration,
+            tuning.gravity,
+            tuning.wall_sliding_speed,
+        ),
+
+        // Off the wall regardless of input
+        State::WallJumping { away, timer } => {
             timer.tick(time.delta());
-            forces.push(JUMPING_FORCE * timer.fraction_remaining());
-            forces.push(direction * JUMPING_SPEED);
+            (
+                *away * tuning.wall_jump_velocity,
+                tuning.air_acceleration,
+                tuning.gravity,
+                tuning.terminal_velocity,
+            )
         }
 
-        State::Falling => {
-            forces.push(direction * FALLING_SPEED);
+        // Each press is good for one stroke
+        State::Swimming => {
+            if jump_timing.since_pressed.take().is_some() {
+                vertical_velocity = tuning.stroke_velocity;
+            }
+
+            (
+                direction * tuning.swimming_speed,
+                tuning.swimming_acceleration,
+                tuning.buoyant_gravity,
+                tuning.sinking_speed,
+            )
         }
 
-        State::Rotating(_axis) => {
+        // Through one-way platforms, as ladders usually lead up through them
+        State::Climbing => {
+            velocity.linvel = (direction * right + vertical_direction * up) * tuning.climbing_speed;
+            controller.filter_groups = Some(CollisionGroups::new(Group::ALL, block::SOLID));
+            controller.translation = Some(velocity.linvel * delta_time);
+            return;
+        }
+
+        State::Rotating(_orientation) => {
             plane_rotation.transition_timer.tick(time.delta());
 
             controller.up = plane_rotation.get() * Vec3::Y;
@@ -196,60 +705,46 @@ fn state_machine(
                 transform.rotation = plane_rotation.get();
             }
 
-            let plane_origin = plane_translation.translation().floor() + 0.5;
-            let plane_normal = plane_rotation.get() * Vec3::Z;
-
-            for (transform, mut visibility) in &mut blocks {
-                *visibility =
-                    if block_intersects_plane(transform.translation(), plane_origin, plane_normal) {
-                        Visibility::Visible
-                    } else {
-                        Visibility::Hidden
-                    }
-            }
+            // Velocity along the old axes means nothing in the new plane
+            velocity.linvel = Vec3::ZERO;
+            controller.translation = None;
+            return;
         }
-    };
 
-    let velocity = &mut player.0.linvel;
-    let force = plane_rotation.get() * forces.iter().sum::<Vec3>();
-    let acceleration = force / mass;
-    let delta_time = time.delta_secs();
-    // Todo: Should be `+=`, physics don't work like this
-    *velocity = acceleration * delta_time;
-    controller.translation = Some(*velocity);
+        State::Dashing { direction, timer } => {
+            timer.tick(time.delta());
 
-    if let Some(output) = &output {
-        let translation = &mut player.1.translation;
-        *translation += output.effective_translation * delta_time;
-    }
-}
+            // Straight through the air, without gravity
+            velocity.linvel = *direction * tuning.dash_speed * right;
+            controller.translation = Some(velocity.linvel * delta_time);
+            return;
+        }
 
-const CORNERS: [Vec3; 8] = [
-    Vec3::new(1.0, 1.0, 1.0),
-    Vec3::new(1.0, 1.0, -1.0),
-    Vec3::new(1.0, -1.0, 1.0),
-    Vec3::new(-1.0, 1.0, 1.0),
-    Vec3::new(1.0, -1.0, -1.0),
-    Vec3::new(-1.0, 1.0, -1.0),
-    Vec3::new(-1.0, -1.0, 1.0),
-    Vec3::new(-1.0, -1.0, -1.0),
-];
-
-/// Plane equation: Ax + By + Cz + D = 0
-fn block_intersects_plane(block_center: Vec3, plane_origin: Vec3, plane_normal: Vec3) -> bool {
-    let plane_point = -plane_normal.dot(plane_origin);
-    let mut above = false;
-    let mut below = false;
-
-    for corner in CORNERS
-        .iter()
-        .map(move |&corner| block_center + (block::SIZE / 2.0) * corner)
-    {
-        let distance = plane_normal.dot(corner) + plane_point;
-
-        above |= distance > EPSILON;
-        below |= distance < -EPSILON;
-    }
+        State::Shifting { from, to, timer } => {
+            timer.tick(time.delta());
+
+            // The plane follows the player's cell, so it moves over once halfway there
+            player_transform.translation = from.lerp(*to, timer.fraction());
+            velocity.linvel = Vec3::ZERO;
+            controller.translation = None;
+            return;
+        }
+    };
 
-    above && below
+    let max_change = acceleration * delta_time;
+    lateral_velocity += (target_velocity - lateral_velocity).clamp(-max_change, max_change);
+    vertical_velocity = (vertical_velocity - gravity * delta_time).max(-terminal_velocity);
+    velocity.linvel = lateral_velocity * right + vertical_velocity * up;
+
+    // One-way platforms only hold the player up while not moving up
+    controller.filter_groups = Some(CollisionGroups::new(
+        Group::ALL,
+        if vertical_velocity > 0.0 {
+            block::SOLID
+        } else {
+            block::SOLID | block::ONE_WAY
+        },
+    ));
+
+    controller.translation = Some(velocity.linvel * delta_time);
 }
@@ -0,0 +1,112 @@
+//! Save slots, written to `~/.untifted/<slot>.json` and read back through the game loader.
+
+use super::abilities::Ability;
+use super::input::{Action, Actions};
+use super::loading::game_loader::Game;
+use super::loading::{LoadingGame, Location};
+use super::plane::Rotation;
+use super::player::Player;
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::{env, io};
+
+const DIRECTORY: &str = ".untifted";
+
+/// The save slot played, the first command line argument or `game`
+#[derive(Resource)]
+pub struct Slot(pub String);
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self(env::args().nth(1).unwrap_or_else(|| "game".into()))
+    }
+}
+
+impl Slot {
+    pub fn path(&self) -> Option<PathBuf> {
+        Some(directory()?.join(format!("{slot}.json", slot = self.0)))
+    }
+}
+
+/// Where saves and settings are kept, if there is a home directory
+pub fn directory() -> Option<PathBuf> {
+    match env::var("HOME") {
+        Ok(home_directory) => Some(PathBuf::from(format!("{home_directory}/{DIRECTORY}"))),
+        Err(error) => {
+            error!("Error finding the home directory: {error}");
+            None
+        }
+    }
+}
+
+/// What the player has unlocked and collected so far
+#[derive(Resource, Default, Debug)]
+pub struct Progress {
+    pub abilities: HashSet<Ability>,
+    pub items: HashSet<String>,
+}
+
+/// Writes the current game to the [`Slot`]
+#[derive(Event)]
+pub struct Save;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<Slot>()
+        .init_resource::<Progress>()
+        .add_observer(save)
+        .add_systems(OnEnter(super::State::Playing), autosave)
+        .add_systems(Update, quicksave.run_if(in_state(super::State::Playing)));
+}
+
+/// Saves every time a room is entered
+fn autosave(mut commands: Commands) {
+    commands.trigger(Save);
+}
+
+fn quicksave(mut commands: Commands, actions: Res<Actions>) {
+    if actions.just_pressed(Action::Save) {
+        commands.trigger(Save);
+    }
+}
+
+fn save(
+    _trigger: Trigger<Save>,
+    slot: Res<Slot>,
+    games: Res<Assets<Game>>,
+    loading_game: Res<LoadingGame>,
+    location: Res<Location>,
+    rotation: Res<Rotation>,
+    progress: Res<Progress>,
+    player: Single<&Transform, With<Player>>,
+) {
+    let game = Game {
+        world: games.get(loading_game.0.id()).unwrap().world.clone(),
+        room: location.room.clone(),
+        spawn_point: location.spawn_point.clone(),
+        translation: Some(player.translation),
+        orientation: rotation.target(),
+        rotation: None,
+        abilities: progress.abilities.clone(),
+        items: progress.items.clone(),
+    };
+
+    let Some(path) = slot.path() else {
+        return;
+    };
+
+    if let Err(error) = write(&path, &game) {
+        error!("Error saving game: {error}");
+    }
+}
+
+fn write(path: &Path, game: &Game) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, game)?;
+    Ok(())
+}